@@ -1,12 +1,12 @@
-use std::fs::File;
 use std::env;
+use std::path::Path;
 
 use ultimate_data_arc::{ParseError, DataArc};
 
 fn main() {
     if let Some(file_name) = env::args().collect::<Vec<String>>().get(1) {
-        if let Ok(file) = File::open(file_name) {
-            match DataArc::new(file) {
+        if Path::new(file_name).exists() {
+            match DataArc::from_path(file_name) {
                 Ok(mut data_arc) => {
                     // TODO: Move this into another example
                     let data = data_arc.get_file("prebuilt:/nro/release/lua2cpp_mewtwo.nro").unwrap();
@@ -20,8 +20,8 @@ fn main() {
                 Err(ParseError::NotDataArc) => {
                     eprintln!("The file is not a valid data.arc file. (magic number was not detected)");
                 }
-                Err(ParseError::InternalError(err)) => {
-                    eprintln!("Internal error, please report the entire error as a bug:\n\n{:?}", err);
+                Err(err) => {
+                    eprintln!("Failed to parse the data.arc, if the file isn't corrupt please report the entire error as a bug:\n\n{}", err);
                 }
             }
         } else {
@@ -0,0 +1,193 @@
+//! A C ABI for using this crate from other languages, enabled by the `ffi` feature.
+//!
+//! To build a shared library: `cargo rustc --release --features ffi --crate-type cdylib`
+//!
+//! Every function returns one of the `UDA_*` codes below as an `i32`.
+//! A `DataArc` is opened with `uda_open`, passed to the other functions as an opaque pointer and freed with `uda_close`.
+//! Strings are NUL terminated UTF-8.
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::os::raw::c_char;
+use std::ptr;
+
+use crate::{DataArc, GetFileError, ParseError};
+
+/// The call succeeded
+pub const UDA_OK: i32 = 0;
+/// A pointer argument was null or a string argument was not valid UTF-8
+pub const UDA_INVALID_ARGUMENT: i32 = 1;
+/// The file could not be opened or read, see `ParseError::Io`/`GetFileError::Io`
+pub const UDA_IO_ERROR: i32 = 2;
+/// The file is not a data.arc, see `ParseError::NotDataArc`
+pub const UDA_NOT_DATA_ARC: i32 = 3;
+/// No file matches the passed name, see `GetFileError::FileNotFound`
+pub const UDA_FILE_NOT_FOUND: i32 = 4;
+/// The passed buffer is too small for the file, the required size has been written to `out_len`
+pub const UDA_BUFFER_TOO_SMALL: i32 = 5;
+/// Any other error: the archive is corrupt, stores data in a way that isn't supported yet, or there is a bug
+/// that needs to be fixed. See the other `ParseError` and `GetFileError` variants.
+pub const UDA_INTERNAL_ERROR: i32 = 6;
+
+/// Open the data.arc at `path`, on success writing a handle to `out_arc` that must be freed with `uda_close`.
+///
+/// # Safety
+/// `path` must be a valid NUL terminated string and `out_arc` must be valid to write to.
+#[no_mangle]
+pub unsafe extern "C" fn uda_open(path: *const c_char, out_arc: *mut *mut DataArc) -> i32 {
+    if path.is_null() || out_arc.is_null() {
+        return UDA_INVALID_ARGUMENT;
+    }
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(_) => return UDA_INVALID_ARGUMENT,
+    };
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return UDA_IO_ERROR,
+    };
+
+    match DataArc::new(file) {
+        Ok(arc) => {
+            *out_arc = Box::into_raw(Box::new(arc));
+            UDA_OK
+        }
+        Err(ParseError::NotDataArc) => UDA_NOT_DATA_ARC,
+        Err(ParseError::Io(_)) => UDA_IO_ERROR,
+        Err(ParseError::Scroll(_))
+        | Err(ParseError::Decompress(_))
+        | Err(ParseError::SizeMismatch { .. })
+        | Err(ParseError::Corrupt(_))
+        | Err(ParseError::SnapshotMismatch(_)) => UDA_INTERNAL_ERROR,
+    }
+}
+
+/// Free a handle returned by `uda_open`. Passing null does nothing.
+///
+/// # Safety
+/// `arc` must be null or a handle returned by `uda_open` that has not already been closed.
+#[no_mangle]
+pub unsafe extern "C" fn uda_close(arc: *mut DataArc) {
+    if !arc.is_null() {
+        drop(Box::from_raw(arc));
+    }
+}
+
+/// Extract the file `name` into `buffer`, writing the size of the file to `out_len`.
+///
+/// If `buffer_len` is smaller than the file, nothing is written to `buffer`, the required size is written to `out_len` and `UDA_BUFFER_TOO_SMALL` is returned.
+/// So the size of a file can be queried by passing a null `buffer` with a `buffer_len` of 0.
+/// Note that the file is extracted on each call, so querying the size first means extracting it twice.
+///
+/// # Safety
+/// `arc` must be a handle returned by `uda_open`, `name` must be a valid NUL terminated string,
+/// `buffer` must be valid to write `buffer_len` bytes to and `out_len` must be valid to write to.
+#[no_mangle]
+pub unsafe extern "C" fn uda_get_file(arc: *mut DataArc, name: *const c_char, buffer: *mut u8, buffer_len: usize, out_len: *mut usize) -> i32 {
+    if arc.is_null() || name.is_null() || out_len.is_null() || (buffer.is_null() && buffer_len != 0) {
+        return UDA_INVALID_ARGUMENT;
+    }
+    let name = match CStr::from_ptr(name).to_str() {
+        Ok(name) => name,
+        Err(_) => return UDA_INVALID_ARGUMENT,
+    };
+
+    write_file((*arc).get_file(name), buffer, buffer_len, out_len)
+}
+
+/// Same as `uda_get_file` but takes the hash40 of the file name.
+///
+/// # Safety
+/// Same as `uda_get_file`.
+#[no_mangle]
+pub unsafe extern "C" fn uda_get_file_by_hash(arc: *mut DataArc, hash: u64, buffer: *mut u8, buffer_len: usize, out_len: *mut usize) -> i32 {
+    if arc.is_null() || out_len.is_null() || (buffer.is_null() && buffer_len != 0) {
+        return UDA_INVALID_ARGUMENT;
+    }
+
+    write_file((*arc).get_file_by_hash(hash), buffer, buffer_len, out_len)
+}
+
+unsafe fn write_file(result: Result<Vec<u8>, GetFileError>, buffer: *mut u8, buffer_len: usize, out_len: *mut usize) -> i32 {
+    match result {
+        Ok(data) => {
+            *out_len = data.len();
+            if data.len() > buffer_len {
+                return UDA_BUFFER_TOO_SMALL;
+            }
+            if !data.is_empty() {
+                ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len());
+            }
+            UDA_OK
+        }
+        Err(GetFileError::FileNotFound) => UDA_FILE_NOT_FOUND,
+        Err(GetFileError::Io(_)) => UDA_IO_ERROR,
+        Err(GetFileError::Scroll(_))
+        | Err(GetFileError::Decompress(_))
+        | Err(GetFileError::SizeMismatch { .. })
+        | Err(GetFileError::InvalidRange { .. })
+        | Err(GetFileError::Unsupported(_))
+        | Err(GetFileError::Corrupt(_)) => UDA_INTERNAL_ERROR,
+    }
+}
+
+#[cfg(all(test, feature = "ffi"))]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+    use crate::hash::hash40;
+    use crate::test_archive::{temp_dir, TestArchive, TestFile};
+
+    #[test]
+    fn open_and_get_file() {
+        let path = temp_dir("ffi").join("data.arc");
+        TestArchive::new(vec!(
+            TestFile::zstd("a/compressed.bin", b"compressed contents"),
+            TestFile::stored("a/stored.bin", b"stored"),
+        )).write_to(&path);
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+
+        unsafe {
+            let mut arc = ptr::null_mut();
+            assert_eq!(uda_open(path.as_ptr(), &mut arc), UDA_OK);
+            assert!(!arc.is_null());
+
+            // Query the size, then extract into a buffer of that size
+            let name = CString::new("a/compressed.bin").unwrap();
+            let mut len = 0;
+            assert_eq!(uda_get_file(arc, name.as_ptr(), ptr::null_mut(), 0, &mut len), UDA_BUFFER_TOO_SMALL);
+            assert_eq!(len, 19);
+            let mut buffer = vec!(0; len);
+            assert_eq!(uda_get_file(arc, name.as_ptr(), buffer.as_mut_ptr(), buffer.len(), &mut len), UDA_OK);
+            assert_eq!(buffer, b"compressed contents");
+
+            let mut buffer = vec!(0; 0x100);
+            assert_eq!(uda_get_file_by_hash(arc, hash40("a/stored.bin"), buffer.as_mut_ptr(), buffer.len(), &mut len), UDA_OK);
+            assert_eq!(&buffer[..len], b"stored");
+
+            let name = CString::new("a/missing.bin").unwrap();
+            assert_eq!(uda_get_file(arc, name.as_ptr(), buffer.as_mut_ptr(), buffer.len(), &mut len), UDA_FILE_NOT_FOUND);
+            assert_eq!(uda_get_file(arc, ptr::null(), buffer.as_mut_ptr(), buffer.len(), &mut len), UDA_INVALID_ARGUMENT);
+
+            uda_close(arc);
+        }
+    }
+
+    #[test]
+    fn open_errors() {
+        let dir = temp_dir("ffi_errors");
+        let not_arc = dir.join("not_data.arc");
+        std::fs::write(&not_arc, b"not a data.arc").unwrap();
+        let not_arc = CString::new(not_arc.to_str().unwrap()).unwrap();
+        let missing = CString::new(dir.join("missing.arc").to_str().unwrap()).unwrap();
+
+        unsafe {
+            let mut arc = ptr::null_mut();
+            assert_eq!(uda_open(not_arc.as_ptr(), &mut arc), UDA_NOT_DATA_ARC);
+            assert_eq!(uda_open(missing.as_ptr(), &mut arc), UDA_IO_ERROR);
+            assert_eq!(uda_open(ptr::null(), &mut arc), UDA_INVALID_ARGUMENT);
+            assert!(arc.is_null());
+        }
+    }
+}
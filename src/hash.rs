@@ -0,0 +1,20 @@
+/// Turns a path into the hash that the data.arc stores in place of the path.
+/// Requires `Send + Sync` so that a `DataArc` holding a hasher can still be sent to and shared with other threads.
+pub trait PathHasher: Send + Sync {
+    fn hash(&self, path: &str) -> u64;
+}
+
+/// The hash used by the data.arc: the crc32 of the path in the lower 32 bits and the length of the path in the upper 8 bits.
+/// This is the default `PathHasher`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hash40;
+
+impl PathHasher for Hash40 {
+    fn hash(&self, path: &str) -> u64 {
+        hash40(path)
+    }
+}
+
+pub(crate) fn hash40(name: &str) -> u64 {
+    crc::crc32::checksum_ieee(name.as_bytes()) as u64 | ((name.len() as u64 & 0xFF) << 32)
+}
@@ -1,38 +1,474 @@
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Error as IOError};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::hash::{Hash, Hasher};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write, Seek, SeekFrom, Error as IOError, ErrorKind};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 use byteorder::{LittleEndian, ByteOrder, ReadBytesExt};
-use failure::{Error, format_err};
-use scroll::{Pread, LE, Error as ScrollError};
+use thiserror::Error;
+use scroll::{Pread, LE};
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
 mod parse;
 use crate::parse::*;
 
+mod hash;
+pub use crate::hash::{PathHasher, Hash40};
+pub use crate::parse::{BigFileIndex, BigFileEntry, BigHashEntry, EntryPair, EntryTriplet, FileEntry, FilePair, HashBucket, TreeEntry};
+
+mod sanitize;
+pub use crate::sanitize::PathSanitizer;
+
+pub mod repack;
+
+mod overlay;
+pub use crate::overlay::OverlayArc;
+
+mod split;
+pub use crate::split::SplitReader;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(test)]
+mod test_archive;
+#[cfg(test)]
+mod tests;
+
+/// Why a data.arc could not be parsed.
+///
 /// The data.arc file starts with a magic number to identify it as a data.arc
-/// It is assumed that any error that occurs on a file starting with the magic number is an internal error
-/// i.e. a bug that needs to be fixed.
-#[derive(Debug)]
+/// It is assumed that any other error that occurs on a file starting with the magic number is either a corrupt file
+/// or a bug that needs to be fixed.
+#[derive(Debug, Error)]
 pub enum ParseError {
     /// The file doesn't start with the magic number 0xabcdef9876543210 so it is not a data.arc file
+    #[error("The file is not a data.arc, it doesn't start with the magic number 0xabcdef9876543210")]
     NotDataArc,
-    /// A bug that needs to be fixed
-    InternalError (Error)
+    /// Reading the file failed, or it ended early
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    /// A header could not be parsed
+    #[error("Failed to parse a header: {0}")]
+    Scroll(#[from] scroll::Error),
+    /// The compressed node section could not be decompressed
+    #[error("Failed to decompress the node section: {0}")]
+    Decompress(io::Error),
+    /// The node section decompressed to a different size than its header says
+    #[error("Expected the node section to decompress to 0x{expected:x} bytes but it decompressed to 0x{actual:x} bytes")]
+    SizeMismatch { expected: u64, actual: u64 },
+    /// The headers and tables are inconsistent with each other or with the size of the file
+    #[error("{0}")]
+    Corrupt(String),
+    /// The snapshot passed to `DataArc::from_snapshot` can't be used with this file
+    #[error("{0}")]
+    SnapshotMismatch(String),
+}
+
+const MAGIC: u64 = 0xabcdef9876543210;
+/// Paths starting with this are bulk files, see `DataArc::get_file`
+const STREAM_SCHEME: &str = "stream:/";
+
+/// Why `extract` failed.
+#[derive(Debug, Error)]
+pub enum ExtractError {
+    /// The data.arc could not be opened or parsed
+    #[error("Failed to open the data.arc: {0}")]
+    Parse(#[from] ParseError),
+    /// The labels file could not be read
+    #[error("Failed to read the labels: {0}")]
+    Labels(io::Error),
+    /// The output directory could not be created
+    #[error("Failed to create the output directory: {0}")]
+    Extract(#[from] GetFileError),
 }
 
 /// Why file data could not be retrieved.
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum GetFileError {
     /// The passed filename or hash does not match any files
+    #[error("The file was not found")]
     FileNotFound,
-    /// A bug that needs to be fixed
-    InternalError (Error)
+    /// Reading or writing file data failed
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    /// An entry in the node section could not be parsed
+    #[error("Failed to parse an entry: {0}")]
+    Scroll(#[from] scroll::Error),
+    /// The file's data could not be decompressed
+    #[error("Failed to decompress the file: {0}")]
+    Decompress(io::Error),
+    /// The file decompressed to a different size than its `FileEntry` says
+    #[error("Expected the file to decompress to 0x{expected:x} bytes but it decompressed to 0x{actual:x} bytes")]
+    SizeMismatch { expected: u64, actual: u64 },
+    /// The range passed to `DataArc::get_file_range` is not within the file
+    #[error("The range {range:?} is outside of the 0x{size:x} byte file")]
+    InvalidRange { range: Range<u64>, size: u64 },
+    /// The file is stored in a way we don't know how to extract yet, or has unknown flags and `UnknownFlagPolicy::Error` is set
+    #[error("{0}")]
+    Unsupported(String),
+    /// An entry refers to something that doesn't exist, e.g. an index past the end of its table
+    #[error("{0}")]
+    Corrupt(String),
+}
+
+/// What `DataArc::get_file` should do when a `TreeEntry` or `FileEntry` has flag bits set that we don't know the meaning of.
+/// Most of the flags are not understood yet, so on a real data.arc anything other than `Ignore` will trigger often.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownFlagPolicy {
+    /// Extract the file as if the unknown bits were not set. This is the default.
+    Ignore,
+    /// Print the unknown bits to stderr then extract the file as if they were not set.
+    Warn,
+    /// Fail with `GetFileError::Unsupported` instead of extracting the file.
+    Error,
+}
+
+/// Why a file can't be extracted by the current resolution logic, as reported by `DataArc::problem_files`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// The tree entry is a redirect that can't be followed, as it redirects more than `MAX_REDIRECT_DEPTH` times or to an out of range tree
+    Redirect,
+    /// The tree entry refers to its sub file through another `FileEntry`, which isn't implemented yet
+    SubFileRedirect,
+    /// The `FileEntry` flags don't match any known compression
+    UnknownCompression,
+    /// An index points past the end of its table, or the file's data lies past the end of the data.arc
+    OutOfRange,
+}
+
+/// Compares the space taken by file data against the size of the file section, as returned by `DataArc::packing_efficiency`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackingReport {
+    /// Size of the file section, from `file_section_offset` to the start of the next section or the end of the data.arc
+    pub file_section_size: u64,
+    /// Number of `FileEntry`s in `sub_files1` and `sub_files2`
+    pub file_entry_count: usize,
+    /// Sum of the compressed size of every `FileEntry`
+    pub total_comp_size: u64,
+}
+
+impl PackingReport {
+    /// `file_section_size - total_comp_size`.
+    /// Positive values are padding or unreferenced data.
+    /// File entries that share data are counted multiple times in `total_comp_size`, so heavy sharing can make this negative.
+    pub fn unaccounted_bytes(&self) -> i64 {
+        self.file_section_size as i64 - self.total_comp_size as i64
+    }
+}
+
+/// The result of `DataArc::sanity_probe`: a quick check of the first entry of each section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SanityProbe {
+    pub checks: Vec<SanityCheck>,
+}
+
+impl SanityProbe {
+    /// Returns true if every check passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    /// The checks that failed
+    pub fn failures(&self) -> impl Iterator<Item=&SanityCheck> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+}
+
+/// A single check made by `DataArc::sanity_probe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SanityCheck {
+    /// Name of the section whose first entry was checked
+    pub section: &'static str,
+    pub passed: bool,
+    /// What was checked, or why it failed
+    pub details: String,
 }
 
-pub struct DataArc {
-    file: File,
+/// Version of the `ParseSnapshot` layout, incremented whenever its contents or their meaning change.
+/// Snapshots are only loaded if their version matches exactly.
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// Everything `DataArc` computes while parsing, from `DataArc::snapshot`, which `DataArc::from_snapshot` uses to skip parsing.
+///
+/// With the `serde` feature this can be serialized to cache it between runs.
+/// The contents are internal and may change between releases of this crate,
+/// so a snapshot from a different schema version is rejected by `DataArc::from_snapshot` and the archive must be parsed again.
+///
+/// Schema version 2 contains the magic number, file size, `ArcHeader`, `NodeHeader`, the `CompressedNodeHeader` if the node section is compressed,
+/// header hash bucket and the offset of each node table.
+/// Version 1 lacked the `CompressedNodeHeader`, so it couldn't reopen archives with a compressed node section.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ParseSnapshot {
+    version: u32,
+    magic: u64,
+    file_len: u64,
     header: ArcHeader,
+    node_header: NodeHeader,
+    compressed_node: Option<CompressedNodeHeader>,
+    first_hash_bucket: HashBucket,
+
+    bulkfile_hash_lookup: usize,
+    bulkfiles_by_name: usize,
+    bulkfile_lookup_to_fileidx: usize,
+    file_pairs: usize,
+    another_hash_table: usize,
+    big_hashes: usize,
+    big_files: usize,
+    folder_hash_lookup: usize,
+    trees: usize,
+    sub_files1: usize,
+    sub_files2: usize,
+    folder_to_big_hash: usize,
+    file_lookup_buckets: usize,
+    file_lookup: usize,
+    numbers: usize,
+}
+
+impl ParseSnapshot {
+    /// The schema version of this snapshot
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+}
+
+/// The result of `DataArc::extract_all`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtractReport {
+    /// Number of files written
+    pub succeeded: usize,
+    /// Path hashes of files stored in a way that can't be extracted yet, see `GetFileError::Unsupported`
+    pub skipped: Vec<u64>,
+    /// Path hashes of files that failed to extract or write for any other reason
+    pub failed: Vec<u64>,
+    /// Path hashes of files sharing data with a file extracted before them, that were linked or not written, see `ExtractOptions::shared_files`.
+    /// Linked files are also counted in `succeeded`.
+    pub shared: Vec<u64>,
+    /// Path hashes of files in `failed` whose stored data was written to a sidecar file, see `ExtractOptions::best_effort`
+    pub sidecars: Vec<u64>,
+}
+
+/// The result of `DataArc::read_folder`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FolderContents {
+    /// The data of each file read, keyed by path hash
+    pub files: HashMap<u64, Vec<u8>>,
+    /// Path hashes of files in the folder that failed to be read, use `DataArc::get_file_by_hash` to find out why
+    pub failed: Vec<u64>,
+}
+
+/// Options for `DataArc::extract_all`.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// Turns labels into paths that are safe to create on disk, `PathSanitizer::default()` by default
+    pub sanitizer: PathSanitizer,
+    /// Write files without a label to `_unknown/0x<hash>.<ext>` instead of `0x<hash>.bin`, so unidentified files are kept apart.
+    /// The extension is guessed from the tree entry's extension hash, which is looked up among the labels:
+    /// either a label that is just the extension, as Hashes.txt contains, or the extension of any labeled path.
+    /// `bin` is used when the extension hash isn't found. Disabled by default.
+    pub unknown_dir: bool,
+    /// What to do with files that share their data with a file extracted before them, `SharedFiles::Write` by default.
+    pub shared_files: SharedFiles,
+    /// For recovering what is left of a corrupt archive: when a file fails to decompress, or decompresses to the wrong size,
+    /// write the data stored for it in the data.arc as is next to where it would have been written.
+    /// The sidecar is the file's path with `.zst` appended for zstd compressed files, or `.raw` for stored files,
+    /// and is cut short if the data extends past the end of the data.arc.
+    /// The file is still recorded as failed, and also in `ExtractReport::sidecars`. Disabled by default.
+    pub best_effort: bool,
+}
+
+/// What `DataArc::extract_all` does with a file whose data is shared with a file it has already extracted,
+/// i.e. whose data is at the same offset and has the same sizes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SharedFiles {
+    /// Write every file separately
+    #[default]
+    Write,
+    /// Hard link the file to the first extracted file with the same data, saving the time and space of writing it again.
+    /// Hard links need `out_dir` to be on a single file system that supports them, e.g. not FAT32 or exFAT,
+    /// if creating the link fails the file is written instead.
+    /// Modifying a linked file modifies every file linked to it.
+    /// Symbolic links aren't used as creating them needs extra privileges on Windows.
+    HardLink,
+    /// Don't write the file at all
+    Skip,
+}
+
+/// Files with identical contents, as found by `DataArc::content_dedup`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DedupReport {
+    /// Each group of two or more files with identical decompressed contents
+    pub groups: Vec<DedupGroup>,
+    /// Path hashes of files that could not be extracted and so were not compared
+    pub failed: Vec<u64>,
+}
+
+impl DedupReport {
+    /// Total compressed bytes that could be saved by storing each group's contents once.
+    pub fn wasted_bytes(&self) -> u64 {
+        self.groups.iter().map(|group| group.wasted_bytes()).sum()
+    }
+}
+
+/// A group of files with identical decompressed contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DedupGroup {
+    /// Path hashes of the files
+    pub hashes: Vec<u64>,
+    /// Decompressed size of the contents
+    pub decomp_size: u64,
+    /// Compressed size of each copy of the contents that is actually stored
+    pub copy_comp_sizes: Vec<u64>,
+}
+
+impl DedupGroup {
+    /// Compressed bytes taken by all but the smallest stored copy.
+    /// Files that already share data are only stored once, so this is 0 if all the files already share data.
+    pub fn wasted_bytes(&self) -> u64 {
+        let total: u64 = self.copy_comp_sizes.iter().sum();
+        total - self.copy_comp_sizes.iter().min().cloned().unwrap_or(0)
+    }
+}
+
+/// Files found to have the same contents while building a `DedupReport`
+struct DedupCandidate {
+    decomp_size: u64,
+    hashes: Vec<u64>,
+    /// Each stored copy of the contents, as offset -> compressed size
+    copies: BTreeMap<u64, u64>,
+}
+
+/// A folder or file in the tree of paths built by `DataArc::build_tree`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathNode {
+    /// The last component of the path, e.g. `model.numdlb`. Empty for the root.
+    pub name: String,
+    /// Hash of the full path, e.g. of `prebuilt:/fighter/mario` for the `mario` folder
+    pub hash: u64,
+    pub kind: PathNodeKind,
+}
+
+/// Whether a `PathNode` is a folder or a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathNodeKind {
+    /// The folders and files in the folder, sorted by name
+    Folder(Vec<PathNode>),
+    /// The file's metadata, None if it can't be resolved, e.g. for a sub file redirect
+    File(Option<FileMetadata>),
+}
+
+/// A folder of a tree being built by `DataArc::build_tree`
+#[derive(Default)]
+struct FolderBuilder {
+    /// Name -> hash and contents
+    folders: BTreeMap<String, (u64, FolderBuilder)>,
+    files: Vec<PathNode>,
+}
+
+impl FolderBuilder {
+    fn build(self, name: String, hash: u64) -> PathNode {
+        let mut children: Vec<PathNode> = self.folders.into_iter()
+            .map(|(name, (hash, folder))| folder.build(name, hash))
+            .chain(self.files)
+            .collect();
+        children.sort_by(|a, b| a.name.cmp(&b.name));
+        PathNode { name, hash, kind: PathNodeKind::Folder(children) }
+    }
+}
+
+/// The first entry of each table in the node section, as returned by `DataArc::sections`.
+/// The entry is read even if the table is empty, in which case it is actually from whatever follows the table.
+#[derive(Debug, Clone)]
+pub struct ArcSections {
+    pub bulkfile_category_info: EntryTriplet,
+    pub bulkfile_hash_lookup: EntryPair,
+    pub bulkfiles_by_name: EntryTriplet,
+    pub bulkfile_lookup_to_fileidx: u32,
+    pub file_pairs: FilePair,
+    pub another_hash_table: EntryTriplet,
+    pub big_hashes: BigHashEntry,
+    pub big_files: BigFileEntry,
+    pub folder_hash_lookup: EntryPair,
+    pub trees: TreeEntry,
+    pub sub_files1: FileEntry,
+    pub sub_files2: FileEntry,
+    pub folder_to_big_hash: EntryPair,
+    pub file_lookup_buckets: HashBucket,
+    pub file_lookup: EntryPair,
+    pub numbers: EntryPair,
+}
+
+/// Where and how a file's data is stored, as returned by `DataArc::file_metadata`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// Absolute offset of the file's data within the data.arc
+    pub offset: u64,
+    /// Size of the data stored in the data.arc
+    pub comp_size: u32,
+    /// Size of the file once decompressed
+    pub decomp_size: u32,
+    pub compression: Compression,
+}
+
+/// How a file's data is compressed, from the `FileEntry` flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// The data is stored as is
+    None,
+    Zstd,
+    /// The compression flags have a value we don't know the meaning of
+    Unknown,
+}
+
+/// The maximum number of redirects followed when resolving a file, to guard against redirect cycles.
+pub const MAX_REDIRECT_DEPTH: u32 = 16;
+
+/// The maximum number of resolved locations kept by the location cache, so looking up every file in a large data.arc can't grow it without bound.
+pub const LOCATION_CACHE_CAPACITY: usize = 0x1000;
+
+/// The stages of parsing a data.arc, reported in this order by `DataArc::new_with_progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenStage {
+    /// The `ArcHeader` at the start of the file has been read
+    HeaderRead,
+    /// The node section has been read into memory
+    NodeRead,
+    /// The offsets of each table in the node section have been computed
+    NodeParsed,
+}
+
+/// A parsed `data.arc`, read from any `Read + Seek` source, a `File` by default.
+pub struct DataArc<R = File> {
+    /// Buffered so that reading many small files that are near each other doesn't need a syscall for each one.
+    file: BufReader<R>,
+    /// Our position in `file`, tracked so that seeks within the buffer can keep the buffer.
+    /// None when unknown, e.g. after a failed read, in which case it is recovered from `file` when next seeking.
+    position: Option<u64>,
+    /// When set, file data is read from here instead of `file`
+    #[cfg(feature = "mmap")]
+    mmap: Option<memmap2::Mmap>,
+    file_len: u64,
+    magic: u64,
+    header: ArcHeader,
+    node_header: NodeHeader,
+    /// Set if the node section is compressed, needed to reread it for a snapshot
+    compressed_node: Option<CompressedNodeHeader>,
     buffer: Vec<u8>,
     first_hash_bucket: HashBucket,
+    unknown_flag_policy: UnknownFlagPolicy,
+    fsync: bool,
+    strict_paths: bool,
+    hasher: Box<dyn PathHasher>,
+    location_cache: HashMap<u64, Location>,
+    /// Paths keyed by their hash, from `load_labels`
+    labels: HashMap<u64, String>,
 
     // offsets into the buffer taken derived from NodeSection
     bulkfile_hash_lookup: usize,
@@ -52,44 +488,111 @@ pub struct DataArc {
     numbers: usize,
 }
 
-impl DataArc {
+impl DataArc<File> {
+    /// Open and parse the `data.arc` at `path`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<DataArc<File>, ParseError> {
+        let file = File::open(path)?;
+        DataArc::new(file)
+    }
+
+    /// Same as `new` but file data is read from a read only memory map of the file instead of by seeking and reading.
+    /// This is faster when extracting many small files.
+    /// The node section is still read into memory as usual.
+    ///
+    /// # Safety
+    /// The file must not be modified, by this or any other process, while the `DataArc` exists.
+    /// Modifying a mapped file is undefined behaviour.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn new_mmap(file: File) -> Result<DataArc<File>, ParseError> {
+        let mmap = memmap2::Mmap::map(&file)?;
+        let mut data_arc = DataArc::new(file)?;
+        data_arc.mmap = Some(mmap);
+        Ok(data_arc)
+    }
+}
+
+impl<R: Read + Seek> DataArc<R> {
     /// Parse the passed `data.arc` file.
-    pub fn new(mut file: File) -> Result<DataArc, ParseError> {
+    pub fn new(file: R) -> Result<DataArc<R>, ParseError> {
+        DataArc::new_with_progress(file, |_| {})
+    }
+
+    /// Same as `new` but calls `progress` after each stage of parsing completes.
+    /// Useful for showing progress while opening a large `data.arc`.
+    pub fn new_with_progress(mut file: R, mut progress: impl FnMut(OpenStage)) -> Result<DataArc<R>, ParseError> {
         if let Ok(magic) = file.read_u64::<LittleEndian>() {
-            if magic != 0xabcdef9876543210 {
+            if magic != MAGIC {
                 return Err(ParseError::NotDataArc);
             }
         } else {
             return Err(ParseError::NotDataArc);
         }
 
-        DataArc::internal_new(file).map_err(|err| ParseError::InternalError(err))
+        DataArc::parse(file, &mut progress)
     }
 
-    pub fn internal_new(mut file: File) -> Result<DataArc, Error> {
-        let mut buffer = vec!(0; ARC_HEADER_SIZE);
-        file.read_exact(&mut buffer)?;
+    pub fn internal_new(file: R) -> Result<DataArc<R>, ParseError> {
+        DataArc::parse(file, &mut |_| {})
+    }
+
+    fn parse(mut file: R, progress: &mut dyn FnMut(OpenStage)) -> Result<DataArc<R>, ParseError> {
+        let magic = LittleEndian::read_u64(&read_section(&mut file, 0, 8, "magic")?);
+
+        let buffer = read_section(&mut file, 8, ARC_HEADER_SIZE, "ArcHeader")?;
         let header: ArcHeader = buffer.pread_with(0, LE)?;
+        progress(OpenStage::HeaderRead);
 
-        file.seek(SeekFrom::Start(header.node_section_offset))?;
+        let file_len = file.seek(SeekFrom::End(0))?;
+        if header.node_section_offset.saturating_add(COMPRESSED_NODE_HEADER_SIZE as u64) > file_len {
+            return Err(ParseError::Corrupt(format!(
+                "The node section offset 0x{:x} is past the end of the file (0x{:x} bytes long), the header is likely corrupt.",
+                header.node_section_offset, file_len
+            )));
+        }
 
-        let mut buffer = vec!(0; COMPRESSED_NODE_HEADER_SIZE);
-        file.read_exact(&mut buffer)?;
+        let buffer = read_section(&mut file, header.node_section_offset, COMPRESSED_NODE_HEADER_SIZE, "CompressedNodeHeader")?;
         let compressed: CompressedNodeHeader = buffer.pread_with(0, LE)?;
 
-        let (node_header, buffer) = if compressed.data_start < 0x100 {
-            // TODO: Handle compressed node
-            unimplemented!()
-        } else {
-            file.seek(SeekFrom::Start(header.node_section_offset))?;
-            let mut buffer = vec!(0; NODE_HEADER_SIZE);
-            file.read_exact(&mut buffer)?;
-            let node_header: NodeHeader = buffer.pread_with(0, LE)?;
-
-            let mut buffer = vec!(0; node_header.file_size as usize - NODE_HEADER_SIZE);
-            file.read_exact(&mut buffer)?;
-            (node_header, buffer)
+        // The node section either starts with a CompressedNodeHeader or directly with the NodeHeader.
+        // Interpreted as a CompressedNodeHeader, an uncompressed node section's `data_start` is the NodeHeader's `file_size`,
+        // which is large as it includes every node table, while a compressed one has a small `data_start`.
+        // Rather than relying on `data_start` alone, check which interpretation is consistent with the size of the file.
+        let available = file_len - header.node_section_offset;
+        let compressed_consistent = compressed.is_consistent(available);
+        // `data_start` is `file_size` when uncompressed
+        let uncompressed_consistent = compressed.data_start as usize >= NODE_HEADER_SIZE && compressed.data_start as u64 <= available;
+        let node_section_offset = header.node_section_offset;
+        let (compressed_node, node_header, buffer) = match (compressed_consistent, uncompressed_consistent) {
+            (true, false) => {
+                let (node_header, buffer) = read_node(&mut file, node_section_offset, Some(&compressed))?;
+                (Some(compressed), node_header, buffer)
+            }
+            (false, true) => {
+                let (node_header, buffer) = read_node(&mut file, node_section_offset, None)?;
+                (None, node_header, buffer)
+            }
+            // A tiny uncompressed node section is implausible, so try compressed first,
+            // falling back to uncompressed if it fails to decompress.
+            (true, true) => match read_node(&mut file, node_section_offset, Some(&compressed)) {
+                Ok((node_header, buffer)) => (Some(compressed), node_header, buffer),
+                Err(compressed_err) => match read_node(&mut file, node_section_offset, None) {
+                    Ok((node_header, buffer)) => (None, node_header, buffer),
+                    Err(uncompressed_err) => {
+                        return Err(ParseError::Corrupt(format!(
+                            "The node section at file offset 0x{:x} could be either compressed or uncompressed, but reading it failed either way. As compressed: {} As uncompressed: {}",
+                            node_section_offset, compressed_err, uncompressed_err
+                        )));
+                    }
+                },
+            },
+            (false, false) => {
+                return Err(ParseError::Corrupt(format!(
+                    "The node section at file offset 0x{:x} is neither a valid compressed nor a valid uncompressed node section: {:x?}, 0x{:x} bytes until the end of the file",
+                    node_section_offset, compressed, available
+                )));
+            }
         };
+        progress(OpenStage::NodeRead);
 
         // The node_header tells us how many entries are in each section.
         // From this we know the end of each section and thus the start of the next section.
@@ -100,22 +603,65 @@ impl DataArc {
         let another_hash_table = file_pairs + FILE_PAIR_SIZE * node_header.music_file_count as usize;
         let big_hashes = another_hash_table + ENTRY_TRIPLET_SIZE * node_header.another_hash_table_size as usize;
         let big_files = big_hashes + BIG_HASH_ENTRY_SIZE * node_header.folder_count as usize;
-        let folder_hash_lookup = big_files + BIG_FILE_ENTRY_SIZE * (node_header.file_count1 + node_header.file_count2) as usize;
+        let folder_hash_lookup = big_files + BIG_FILE_ENTRY_SIZE * node_header.big_file_count() as usize;
         let trees = folder_hash_lookup + ENTRY_PAIR_SIZE * node_header.hash_folder_count as usize;
         let sub_files1 = trees + TREE_ENTRY_SIZE * node_header.tree_count as usize;
         let sub_files2 = sub_files1 + FILE_ENTRY_SIZE * node_header.sub_files1_count as usize;
         let folder_to_big_hash = sub_files2 + FILE_ENTRY_SIZE * node_header.sub_files2_count as usize;
         let file_lookup_buckets = folder_to_big_hash + ENTRY_PAIR_SIZE * node_header.folder_count as usize;
-        let first_hash_bucket: HashBucket = (&buffer[file_lookup_buckets..]).pread_with(0, LE)?;
+
+        // The format only gives us counts, so padding between sections (e.g. from a third party packer)
+        // silently shifts every following section.
+        // We can't detect padding in the middle of the node section, the shifted tables just read the wrong bytes.
+        // It is only caught once a shifted table ends past the end of the node section, so report which one.
+        check_section_ends(&buffer, &[
+            ("bulkfile_category_info", bulkfile_hash_lookup),
+            ("bulkfile_hash_lookup", bulkfiles_by_name),
+            ("bulkfiles_by_name", bulkfile_lookup_to_fileidx),
+            ("bulkfile_lookup_to_fileidx", file_pairs),
+            ("file_pairs", another_hash_table),
+            ("another_hash_table", big_hashes),
+            ("big_hashes", big_files),
+            ("big_files", folder_hash_lookup),
+            ("folder_hash_lookup", trees),
+            ("trees", sub_files1),
+            ("sub_files1", sub_files2),
+            ("sub_files2", folder_to_big_hash),
+            ("folder_to_big_hash", file_lookup_buckets),
+            ("first_hash_bucket", file_lookup_buckets + HASH_BUCKET_SIZE),
+        ])?;
+
+        // The first HashBucket is a header rather than an actual bucket: its `num_entries` is the number of buckets that follow it.
+        // So the bucket table is `num_entries + 1` HashBuckets long.
+        // Every archive we have seen uses this layout, we have not found a version that stores the bucket count differently.
+        let first_hash_bucket: HashBucket = slice_at(&buffer, file_lookup_buckets, HASH_BUCKET_SIZE)
+            .map_err(|x| ParseError::Corrupt(format!("Failed reading the header HashBucket: {}", x)))?
+            .pread_with(0, LE)?;
+        // Zero padding before the bucket table reads as a header saying there are no buckets, which would fail every lookup
+        if first_hash_bucket.num_entries == 0 && node_header.file_lookup_count > 0 {
+            return Err(ParseError::Corrupt(format!(
+                "The file_lookup_buckets section at 0x{:x} has no buckets but there are 0x{:x} file_lookup entries. The archive may contain padding between sections.",
+                file_lookup_buckets, node_header.file_lookup_count
+            )));
+        }
         let file_lookup = file_lookup_buckets + HASH_BUCKET_SIZE * (first_hash_bucket.num_entries as usize + 1);
         let numbers = file_lookup + ENTRY_PAIR_SIZE * node_header.file_lookup_count as usize;
 
-        Ok(DataArc {
-            file,
+        check_section_ends(&buffer, &[
+            ("file_lookup_buckets", file_lookup),
+            ("file_lookup", numbers),
+        ])?;
+        progress(OpenStage::NodeParsed);
+
+        let snapshot = ParseSnapshot {
+            version: SNAPSHOT_VERSION,
+            magic,
+            file_len,
             header,
-            buffer,
+            node_header,
+            compressed_node,
+            first_hash_bucket,
 
-            // offsets into the buffer taken derived from NodeSection
             bulkfile_hash_lookup,
             bulkfiles_by_name,
             bulkfile_lookup_to_fileidx,
@@ -131,12 +677,196 @@ impl DataArc {
             file_lookup_buckets,
             file_lookup,
             numbers,
+        };
+        Ok(DataArc::from_parts(file, buffer, snapshot))
+    }
 
-            first_hash_bucket,
-        })
+    /// Capture the result of parsing, so that it can be stored and later passed to `DataArc::from_snapshot` to skip parsing.
+    pub fn snapshot(&self) -> ParseSnapshot {
+        ParseSnapshot {
+            version: SNAPSHOT_VERSION,
+            magic: self.magic,
+            file_len: self.file_len,
+            header: self.header.clone(),
+            node_header: self.node_header.clone(),
+            compressed_node: self.compressed_node.clone(),
+            first_hash_bucket: self.first_hash_bucket.clone(),
+
+            bulkfile_hash_lookup: self.bulkfile_hash_lookup,
+            bulkfiles_by_name: self.bulkfiles_by_name,
+            bulkfile_lookup_to_fileidx: self.bulkfile_lookup_to_fileidx,
+            file_pairs: self.file_pairs,
+            another_hash_table: self.another_hash_table,
+            big_hashes: self.big_hashes,
+            big_files: self.big_files,
+            folder_hash_lookup: self.folder_hash_lookup,
+            trees: self.trees,
+            sub_files1: self.sub_files1,
+            sub_files2: self.sub_files2,
+            folder_to_big_hash: self.folder_to_big_hash,
+            file_lookup_buckets: self.file_lookup_buckets,
+            file_lookup: self.file_lookup,
+            numbers: self.numbers,
+        }
+    }
+
+    /// Open the data.arc using the result of a previous parse of the same file, from `DataArc::snapshot`.
+    /// The node section is still read from the file but none of the tables are parsed.
+    ///
+    /// Fails if the snapshot was created by an incompatible version of this crate, or if the file's size doesn't match the snapshot.
+    pub fn from_snapshot(mut file: R, snapshot: ParseSnapshot) -> Result<DataArc<R>, ParseError> {
+        DataArc::internal_from_snapshot(&mut file, &snapshot)
+            .map(|buffer| DataArc::from_parts(file, buffer, snapshot))
+    }
+
+    fn internal_from_snapshot(file: &mut R, snapshot: &ParseSnapshot) -> Result<Vec<u8>, ParseError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(ParseError::SnapshotMismatch(format!("The snapshot has version {} but only version {} is supported", snapshot.version, SNAPSHOT_VERSION)));
+        }
+
+        let file_len = file.seek(SeekFrom::End(0))?;
+        if file_len != snapshot.file_len {
+            return Err(ParseError::SnapshotMismatch(format!("The snapshot is of a 0x{:x} byte data.arc but the file is 0x{:x} bytes", snapshot.file_len, file_len)));
+        }
+
+        let (_, buffer) = read_node(file, snapshot.header.node_section_offset, snapshot.compressed_node.as_ref())?;
+        Ok(buffer)
+    }
+
+    fn from_parts(file: R, buffer: Vec<u8>, snapshot: ParseSnapshot) -> DataArc<R> {
+        DataArc {
+            file: BufReader::new(file),
+            position: None,
+            #[cfg(feature = "mmap")]
+            mmap: None,
+            file_len: snapshot.file_len,
+            magic: snapshot.magic,
+            header: snapshot.header,
+            node_header: snapshot.node_header,
+            compressed_node: snapshot.compressed_node,
+            buffer,
+
+            // offsets into the buffer taken derived from NodeSection
+            bulkfile_hash_lookup: snapshot.bulkfile_hash_lookup,
+            bulkfiles_by_name: snapshot.bulkfiles_by_name,
+            bulkfile_lookup_to_fileidx: snapshot.bulkfile_lookup_to_fileidx,
+            file_pairs: snapshot.file_pairs,
+            another_hash_table: snapshot.another_hash_table,
+            big_hashes: snapshot.big_hashes,
+            big_files: snapshot.big_files,
+            folder_hash_lookup: snapshot.folder_hash_lookup,
+            trees: snapshot.trees,
+            sub_files1: snapshot.sub_files1,
+            sub_files2: snapshot.sub_files2,
+            folder_to_big_hash: snapshot.folder_to_big_hash,
+            file_lookup_buckets: snapshot.file_lookup_buckets,
+            file_lookup: snapshot.file_lookup,
+            numbers: snapshot.numbers,
+
+            first_hash_bucket: snapshot.first_hash_bucket,
+            unknown_flag_policy: UnknownFlagPolicy::Ignore,
+            fsync: false,
+            strict_paths: false,
+            hasher: Box::new(Hash40),
+            location_cache: HashMap::new(),
+            labels: HashMap::new(),
+        }
     }
 
+    /// Extract the file at `file_name`, e.g. `prebuilt:/fighter/mario/model/body/c00/model.numdlb`.
+    ///
+    /// The path's scheme picks the tables the file is looked up in:
+    /// *   `stream:/` paths are looked up as bulk files in the music file section, as by `get_bulkfile_by_name`,
+    ///     falling back to the file section if no bulk file has that name.
+    /// *   `prebuilt:/` paths, paths with any other scheme and paths without a scheme are looked up in the file section.
+    ///
+    /// The full path, including the scheme, is always what gets hashed.
     pub fn get_file(&mut self, file_name: &str) -> Result<Vec<u8>, GetFileError> {
+        if file_name.starts_with(STREAM_SCHEME) {
+            match self.get_bulkfile_by_name(file_name) {
+                Err(GetFileError::FileNotFound) => { }
+                result => return result,
+            }
+        }
+
+        let hash = self.hasher.hash(file_name);
+        if self.strict_paths {
+            self.check_path_components(file_name, hash)?;
+        }
+        self.get_file_by_hash(hash)
+    }
+
+    /// Same as `get_file` but takes the hash40 of the file name.
+    pub fn get_file_by_hash(&mut self, hash: u64) -> Result<Vec<u8>, GetFileError> {
+        let mut data = vec!();
+        self.get_file_by_hash_into(hash, &mut data)?;
+        Ok(data)
+    }
+
+    /// Same as `get_file_by_hash` but also returns the file's metadata, as from `file_metadata_by_hash`.
+    /// The file is only resolved and located once, so this is cheaper than calling both.
+    pub fn get_file_full(&mut self, hash: u64) -> Result<(Vec<u8>, FileMetadata), GetFileError> {
+        let (sub_file, offset, compression) = self.locate_data(hash)?;
+        let mut data = vec!();
+        self.read_data_into(&sub_file, offset, compression, &mut data)?;
+        let metadata = FileMetadata {
+            offset,
+            comp_size: sub_file.comp_size,
+            decomp_size: sub_file.decomp_size,
+            compression,
+        };
+        Ok((data, metadata))
+    }
+
+    /// Same as `get_file_by_hash` but borrows the file's data instead of copying it when possible.
+    ///
+    /// Data can only be borrowed from a memory map, so `Cow::Borrowed` is only returned with the `mmap` feature for a
+    /// `DataArc` opened by `new_mmap`, and only for files stored uncompressed.
+    /// Compressed files, and every file of a `DataArc` reading through `R`, are read into a new buffer as by `get_file_by_hash`.
+    pub fn get_file_cow(&mut self, hash: u64) -> Result<Cow<'_, [u8]>, GetFileError> {
+        #[cfg(feature = "mmap")]
+        {
+            if self.mmap.is_some() {
+                let (sub_file, offset, compression) = self.locate_data(hash)?;
+                if compression == Compression::None {
+                    let mmap = self.mmap.as_ref().unwrap();
+                    return Ok(Cow::Borrowed(mmap_slice(mmap, offset, sub_file.decomp_size as u64)?));
+                }
+            }
+        }
+
+        self.get_file_by_hash(hash).map(Cow::Owned)
+    }
+
+    /// Decompress the file directly into `out`, returning the number of bytes written.
+    ///
+    /// Unlike `get_file` the whole file is never held in memory, so this is better for extracting large files.
+    /// If the file turns out to be corrupt, the data written to `out` before the error was detected is not removed.
+    /// Paths are looked up in the same tables as by `get_file`, bulk files are read into memory before being written to `out`.
+    ///
+    /// `out` can be any writer, e.g. pass `&mut io::stdout().lock()` to pipe a file to another program.
+    /// Data is written in chunks of up to 64 KiB, so there is no need to wrap `out` in a `BufWriter`.
+    pub fn get_file_into(&mut self, file_name: &str, out: &mut impl Write) -> Result<u64, GetFileError> {
+        if file_name.starts_with(STREAM_SCHEME) {
+            match self.get_bulkfile_by_name(file_name) {
+                Err(GetFileError::FileNotFound) => { }
+                result => {
+                    let data = result?;
+                    out.write_all(&data)?;
+                    return Ok(data.len() as u64);
+                }
+            }
+        }
+
+        let hash = self.hasher.hash(file_name);
+        if self.strict_paths {
+            self.check_path_components(file_name, hash)?;
+        }
+        self.get_file_by_hash_into(hash, out)
+    }
+
+    /// Same as `get_file_into` but takes the hash40 of the file name.
+    pub fn get_file_by_hash_into(&mut self, hash: u64, out: &mut impl Write) -> Result<u64, GetFileError> {
         // Actual file data is stored at self.header.file_section_offset, however this contains no file metadata.
         // The metadata is stored at `self.header.node_section_offset`.
         // However the metadata doesnt actually contain filenames etc. it only contains hashes of this data.
@@ -155,100 +885,1304 @@ impl DataArc {
         // The values at that index are sorted by hash so we use a binary search on the hash to find the correct `EntryPair`.
         // And finally an offset to `self.trees` is in `self.file_lookup`.
 
-        let hash = hash40(file_name);
-        let num_buckets = self.first_hash_bucket.num_entries;
-        let offset = self.file_lookup_buckets + HASH_BUCKET_SIZE * (hash % num_buckets as u64 + 1) as usize;
-        let bucket: HashBucket = self.buffer[offset..].pread_with(0, LE).map_err(|x: ScrollError| GetFileError::InternalError(x.into()))?;
-        let entry = self.bucket_search(hash, &bucket)?;
-        let tree = read_tree_entry(&self.buffer[self.trees + TREE_ENTRY_SIZE * entry.meta as usize..]);
-        // TODO: Hmmm I wonder if I'm supposed to further check the individual checksums of the tree
+        let (sub_file, offset, compression) = self.locate_data(hash)?;
+        self.read_data_into(&sub_file, offset, compression, out)
+    }
 
-        if tree.redirect() {
-            unimplemented!();
+    /// Write the sub file's data starting at `offset`, as returned by `locate_data`, to `out`, decompressing it if needed.
+    fn read_data_into(&mut self, sub_file: &FileEntry, offset: u64, compression: Compression, out: &mut impl Write) -> Result<u64, GetFileError> {
+        let written = if compression == Compression::None {
+            copy_all(self.raw_reader(offset, sub_file.decomp_size as u64)?, out, GetFileError::Io)?
+        } else {
+            let decoder = zstd::stream::Decoder::with_buffer(self.raw_reader(offset, sub_file.comp_size as u64)?)
+                .map_err(GetFileError::Decompress)?;
+            copy_all(decoder, out, GetFileError::Decompress)?
+        };
+
+        if written != sub_file.decomp_size as u64 {
+            return Err(GetFileError::SizeMismatch { expected: sub_file.decomp_size as u64, actual: written });
         }
+        Ok(written)
+    }
 
-        let suboffset_index = if tree.suboffset_index() {
-            tree.suboffset_index as usize
-        } else {
-            let file_entry: FileEntry = (&self.buffer[self.sub_files1 + FILE_ENTRY_SIZE * tree.ext.meta as usize..])
-                .pread_with(0, LE).map_err(|x: ScrollError| GetFileError::InternalError(x.into()))?;
-            if file_entry.suboffset_redir() {
-                tree.ext.meta as usize + file_entry.suboffset_tree_index()
-            } else {
-                tree.ext.meta as usize
+    /// Read a bulk file, e.g. streamed music, from the music file section.
+    /// Bulk files are looked up through their own tables and are never decompressed, the raw stored bytes are returned.
+    ///
+    /// The hashed name is searched for in `bulkfiles_by_name`, whose `meta` is an index into `bulkfile_lookup_to_fileidx`,
+    /// which in turn holds the index of the `FilePair` in `file_pairs` that gives the size and offset of the data
+    /// relative to `music_file_section_offset`.
+    pub fn get_bulkfile_by_name(&mut self, name: &str) -> Result<Vec<u8>, GetFileError> {
+        let hash = self.hasher.hash(name);
+        let entry = (0..self.node_header.part1_count as usize)
+            .map(|i| self.node_slice(self.bulkfiles_by_name + ENTRY_TRIPLET_SIZE * i, ENTRY_TRIPLET_SIZE).map(read_triplet))
+            .find(|entry| entry.as_ref().map_or(true, |entry| entry.hash == hash))
+            .ok_or(GetFileError::FileNotFound)??;
+
+        check_index("bulkfile_lookup_to_fileidx", entry.meta, self.node_header.part2_count)?;
+        let file_index = LittleEndian::read_u32(self.node_slice(self.bulkfile_lookup_to_fileidx + 4 * entry.meta as usize, 4)?);
+
+        check_index("file_pairs", file_index, self.node_header.music_file_count)?;
+        let file_pair: FilePair = self.node_slice(self.file_pairs + FILE_PAIR_SIZE * file_index as usize, FILE_PAIR_SIZE)?
+            .pread_with(0, LE)?;
+
+        let offset = self.header.music_file_section_offset.checked_add(file_pair.offset)
+            .ok_or_else(|| GetFileError::Corrupt(format!("Failed to extract bulk file 0x{:x}: Its offset 0x{:x} overflows", hash, file_pair.offset)))?;
+        if offset.saturating_add(file_pair.size) > self.file_len {
+            return Err(GetFileError::Corrupt(format!(
+                "Failed to extract bulk file 0x{:x}: Its 0x{:x} bytes at 0x{:x} lie past the end of the data.arc", hash, file_pair.size, offset
+            )));
+        }
+
+        let mut buffer = vec!(0; file_pair.size as usize);
+        self.read_exact_at(offset, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Override where file data is read from, for archives whose file section has been relocated, e.g. by a mod loader setup.
+    /// The node section is still read from its original location, so all offsets within the file section must be unchanged.
+    ///
+    /// Nothing checks that the new offset is correct, a wrong offset will result in garbage data or decompression errors.
+    pub fn set_file_section_offset(&mut self, offset: u64) {
+        self.header.file_section_offset = offset;
+    }
+
+    /// The magic number the data.arc starts with.
+    /// The only known magic number is `0xabcdef9876543210`, which `DataArc::new` checks for.
+    ///
+    /// There is no `format_version` method as only one magic number and one layout are known,
+    /// so there is nothing to distinguish versions by yet.
+    pub fn magic(&self) -> u64 {
+        self.magic
+    }
+
+    /// Set the hasher used to turn file names into hashes, by default `Hash40` is used.
+    /// This only affects methods that take a file name, methods that take a hash use it as is.
+    pub fn set_hasher(&mut self, hasher: impl PathHasher + 'static) {
+        self.hasher = Box::new(hasher);
+    }
+
+    /// Returns the bytes in `range` of the decompressed file.
+    ///
+    /// A zstd frame can't be randomly accessed, so for compressed files everything before `range.start` still needs to be decompressed and is discarded.
+    /// The compressed data is streamed though, so only the data needed to decompress up to `range.end` is read,
+    /// and only the bytes in `range` are kept in memory.
+    pub fn get_file_range(&mut self, hash: u64, range: Range<u64>) -> Result<Vec<u8>, GetFileError> {
+        let (sub_file, offset, compression) = self.locate_data(hash)?;
+        if range.start > range.end || range.end > sub_file.decomp_size as u64 {
+            return Err(GetFileError::InvalidRange { range, size: sub_file.decomp_size as u64 });
+        }
+
+        let mut buffer = vec!(0; (range.end - range.start) as usize);
+        if compression == Compression::None {
+            self.read_exact_at(offset.saturating_add(range.start), &mut buffer)?;
+            return Ok(buffer);
+        }
+
+        let mut decoder = zstd::stream::Decoder::with_buffer(self.raw_reader(offset, sub_file.comp_size as u64)?)
+            .map_err(GetFileError::Decompress)?;
+        io::copy(&mut (&mut decoder).take(range.start), &mut io::sink())
+            .map_err(GetFileError::Decompress)?;
+        decoder.read_exact(&mut buffer)
+            .map_err(GetFileError::Decompress)?;
+
+        Ok(buffer)
+    }
+
+    /// Decompress the file to check its decompressed size matches the size stored in the archive, without returning the data.
+    /// Returns `Ok(false)` if the sizes don't match, and an error if the data can't be read or decompressed.
+    ///
+    /// The data is streamed through a fixed size buffer rather than being collected, so checking a large file doesn't allocate its size.
+    pub fn verify_file(&mut self, hash: u64) -> Result<bool, GetFileError> {
+        match self.get_file_by_hash_into(hash, &mut io::sink()) {
+            Ok(_) => Ok(true),
+            Err(GetFileError::SizeMismatch { .. }) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Resolve the hash to the entries locating its data, reusing the result of any previous resolution of the same hash.
+    fn locate(&mut self, hash: u64) -> Result<Location, GetFileError> {
+        if let Some(location) = self.location_cache.get(&hash) {
+            return Ok(location.clone());
+        }
+
+        let tree = self.lookup_tree(hash)?;
+        let location = self.resolve(tree)?;
+        // Dropping everything is cheaper than tracking usage, and repeated lookups of a working set refill it quickly
+        if self.location_cache.len() >= LOCATION_CACHE_CAPACITY {
+            self.location_cache.clear();
+        }
+        self.location_cache.insert(hash, location.clone());
+        Ok(location)
+    }
+
+    /// Resolving a hash to the location of its data is cached, this clears that cache to free its memory.
+    /// The cache is only keyed by hash and the node section is never modified, so it never needs to be cleared for correctness.
+    /// It never holds more than `LOCATION_CACHE_CAPACITY` locations, it is emptied whenever it fills up.
+    pub fn clear_location_cache(&mut self) {
+        self.location_cache.clear();
+    }
+
+    /// Read `len` raw bytes starting `offset` bytes from the start of the data.arc.
+    ///
+    /// No interpretation of the bytes is done, this is just for probing regions of the archive that aren't understood yet.
+    /// Fails if the range extends past the end of the file.
+    pub fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        if offset.saturating_add(len as u64) > self.file_len {
+            return Err(IOError::new(ErrorKind::UnexpectedEof, format!(
+                "Tried to read 0x{:x} bytes at 0x{:x} but the data.arc is only 0x{:x} bytes long", len, offset, self.file_len
+            )));
+        }
+
+        let mut buffer = vec!(0; len);
+        self.read_raw(offset, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Find the `FileEntry`, absolute offset and compression of a file.
+    /// Fails if the compression is unknown, so the returned compression is never `Compression::Unknown`.
+    fn locate_data(&mut self, hash: u64) -> Result<(FileEntry, u64, Compression), GetFileError> {
+        let Location { tree, sub_file, big_file, .. } = self.locate(hash)?;
+        self.check_unknown_flags(hash, &tree, &sub_file)?;
+
+        let compression = compression(&sub_file);
+        if compression == Compression::Unknown {
+            return Err(GetFileError::Unsupported(format!("Failed to extract 0x{:x}: Unknown compression", hash)));
+        }
+
+        let offset = self.file_offset(&sub_file, &big_file)?;
+        data_end(hash, offset, &sub_file)?;
+        Ok((sub_file, offset, compression))
+    }
+
+    fn read_exact_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), GetFileError> {
+        Ok(self.read_raw(offset, buffer)?)
+    }
+
+    fn read_raw(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+        #[cfg(feature = "mmap")]
+        {
+            if let Some(mmap) = &self.mmap {
+                buffer.copy_from_slice(mmap_slice(mmap, offset, buffer.len() as u64)?);
+                return Ok(());
+            }
+        }
+
+        self.seek_raw(offset)?;
+        self.file.read_exact(buffer)?;
+        self.position = Some(offset + buffer.len() as u64);
+        Ok(())
+    }
+
+    /// A reader of the `len` raw bytes starting `offset` bytes from the start of the data.arc.
+    /// Reading past the end of the data.arc fails with `ErrorKind::UnexpectedEof`, whether or not a memory map is used.
+    fn raw_reader(&mut self, offset: u64, len: u64) -> io::Result<Box<dyn BufRead + '_>> {
+        #[cfg(feature = "mmap")]
+        {
+            if let Some(mmap) = &self.mmap {
+                return Ok(Box::new(mmap_slice(mmap, offset, len)?));
+            }
+        }
+
+        // The fields are borrowed individually as the memory map may still be borrowed here.
+        seek_buffered(&mut self.file, &mut self.position, offset)?;
+        // The reader doesn't track how far through the file it reads, so our position is unknown afterwards.
+        self.position = None;
+        Ok(Box::new((&mut self.file).take(len)))
+    }
+
+    /// Seek `file` to `offset`. `position` is left as None, the caller must set it once it knows where it has read to.
+    fn seek_raw(&mut self, offset: u64) -> io::Result<()> {
+        seek_buffered(&mut self.file, &mut self.position, offset)
+    }
+
+    /// Extract every file directly in the folder with the passed hash, keyed by path hash.
+    /// Files in subfolders are not included. Returns no files if no files are in the folder.
+    ///
+    /// A file that fails to be read is listed in `FolderContents::failed` instead of failing the whole folder.
+    /// An error is only returned if the tree entries can't be read to find the files in the folder.
+    ///
+    /// All of the files are held in memory at once, so this can use a lot of memory for large folders.
+    pub fn read_folder(&mut self, folder_hash: u64) -> Result<FolderContents, GetFileError> {
+        let mut hashes = vec!();
+        for i in 0..self.node_header.tree_count {
+            let tree = self.tree(TreeIndex(i))?;
+            if tree.folder.hash == folder_hash {
+                hashes.push(tree.path.hash);
+            }
+        }
+
+        let mut contents = FolderContents::default();
+        for hash in hashes {
+            match self.get_file_by_hash(hash) {
+                Ok(data) => { contents.files.insert(hash, data); }
+                Err(_) => contents.failed.push(hash),
+            }
+        }
+        Ok(contents)
+    }
+
+    /// Same as `get_file_by_hash` but takes the hash40 as the 5 bytes it is stored as on disk.
+    /// The bytes are little endian, i.e. `hash[0]` is the least significant byte.
+    pub fn get_file_by_hash_bytes(&mut self, hash: [u8; 5]) -> Result<Vec<u8>, GetFileError> {
+        let hash = LittleEndian::read_u64(&[hash[0], hash[1], hash[2], hash[3], hash[4], 0, 0, 0]);
+        self.get_file_by_hash(hash)
+    }
+
+    /// Get the offset, sizes and compression of the file without reading its data.
+    /// The file name is checked the same way as by `get_file`, including `set_strict_paths`.
+    pub fn file_metadata(&mut self, file_name: &str) -> Result<FileMetadata, GetFileError> {
+        let hash = self.hasher.hash(file_name);
+        if self.strict_paths {
+            self.check_path_components(file_name, hash)?;
+        }
+        self.file_metadata_by_hash(hash)
+    }
+
+    /// Same as `file_metadata` but takes the hash40 of the file name.
+    pub fn file_metadata_by_hash(&mut self, hash: u64) -> Result<FileMetadata, GetFileError> {
+        let Location { sub_file, big_file, .. } = self.locate(hash)?;
+        let compression = compression(&sub_file);
+        Ok(FileMetadata {
+            offset: self.file_offset(&sub_file, &big_file)?,
+            comp_size: sub_file.comp_size,
+            decomp_size: sub_file.decomp_size,
+            compression,
+        })
+    }
+
+    /// Extract the file to `path`.
+    ///
+    /// The data is first written to `path` with `.part` appended, which is then renamed to `path` once fully written.
+    /// So if extraction is interrupted there will never be a partially written file at `path`, only at the `.part` path.
+    /// If the write fails the `.part` file is removed.
+    ///
+    /// A crash can still lose data that the OS hasn't flushed to disk yet, use `set_fsync(true)` to prevent that.
+    pub fn extract_file(&mut self, file_name: &str, path: &Path) -> Result<(), GetFileError> {
+        let data = self.get_file(file_name)?;
+        Ok(write_atomic(path, self.fsync, |file| file.write_all(&data))?)
+    }
+
+    /// Same as `extract_file` but takes the hash40 of the file name, e.g. from `DataArc::hashes`.
+    pub fn extract_by_hash(&mut self, hash: u64, path: &Path) -> Result<(), GetFileError> {
+        let data = self.get_file_by_hash(hash)?;
+        Ok(write_atomic(path, self.fsync, |file| file.write_all(&data))?)
+    }
+
+    /// Extract every file in the archive into `out_dir`, creating subdirectories as needed.
+    ///
+    /// Files are written to their path in `labels`, or in the labels loaded by `load_labels` if `labels` is None,
+    /// made safe to create on disk by `options.sanitizer`.
+    /// Files without a label are written to `0x<hash>.bin` in `out_dir`, or to `_unknown/0x<hash>.<ext>` with `options.unknown_dir`.
+    /// Each file is written the same way as `extract_by_hash`.
+    ///
+    /// A file failing to extract doesn't stop the others from being extracted, instead it is recorded in the returned report.
+    /// Only failing to create `out_dir` itself returns an error.
+    pub fn extract_all(&mut self, out_dir: &Path, labels: Option<&HashMap<u64, String>>, options: &ExtractOptions) -> Result<ExtractReport, GetFileError> {
+        fs::create_dir_all(out_dir)?;
+
+        let labels = labels.unwrap_or(&self.labels);
+        let extensions = if options.unknown_dir { self.extensions(labels) } else { HashMap::new() };
+        let files: Vec<(u64, PathBuf)> = self.hashes().map(|hash| {
+            let relative = labels.get(&hash)
+                .map(|name| options.sanitizer.sanitize(name))
+                .filter(|path| path.components().next().is_some())
+                .unwrap_or_else(|| if options.unknown_dir {
+                    let ext = self.tree_entry(hash)
+                        .and_then(|tree| extensions.get(&tree.ext.hash))
+                        .map(|ext| ext.as_str())
+                        .unwrap_or("bin");
+                    options.sanitizer.sanitize(&format!("_unknown/0x{:x}.{}", hash, ext))
+                } else {
+                    PathBuf::from(format!("0x{:x}.bin", hash))
+                });
+            (hash, out_dir.join(relative))
+        }).collect();
+
+        let mut report = ExtractReport::default();
+        // The path of the first file extracted with each offset and sizes
+        let mut extracted: HashMap<(u64, u32, u32), PathBuf> = HashMap::new();
+        for (hash, path) in files {
+            let data_key = match options.shared_files {
+                SharedFiles::Write => None,
+                SharedFiles::HardLink | SharedFiles::Skip => self.locate(hash).ok().and_then(|location| {
+                    let offset = self.file_offset(&location.sub_file, &location.big_file).ok()?;
+                    Some((offset, location.sub_file.comp_size, location.sub_file.decomp_size))
+                }),
+            };
+
+            if let Some(first) = data_key.and_then(|key| extracted.get(&key)) {
+                if options.shared_files == SharedFiles::Skip {
+                    report.shared.push(hash);
+                    continue;
+                }
+                if create_parent(&path).and_then(|_| hard_link(first, &path)).is_ok() {
+                    report.succeeded += 1;
+                    report.shared.push(hash);
+                    continue;
+                }
+            }
+
+            let result = create_parent(&path)
+                .map_err(GetFileError::Io)
+                .and_then(|_| self.extract_by_hash(hash, &path));
+            match result {
+                Ok(()) => {
+                    report.succeeded += 1;
+                    if let Some(key) = data_key {
+                        extracted.insert(key, path);
+                    }
+                }
+                Err(GetFileError::Unsupported(_)) => report.skipped.push(hash),
+                Err(err) => {
+                    report.failed.push(hash);
+                    let corrupt_data = matches!(err, GetFileError::Decompress(_) | GetFileError::SizeMismatch { .. });
+                    if options.best_effort && corrupt_data && self.write_sidecar(hash, &path).is_ok() {
+                        report.sidecars.push(hash);
+                    }
+                }
             }
+        }
+        Ok(report)
+    }
+
+    /// Write the data stored for the file, without decompressing it, to `path` with `.zst` or `.raw` appended, for `ExtractOptions::best_effort`.
+    fn write_sidecar(&mut self, hash: u64, path: &Path) -> Result<(), GetFileError> {
+        let (sub_file, offset, compression) = self.locate_data(hash)?;
+        let extension = match compression {
+            Compression::Zstd => ".zst",
+            _ => ".raw",
         };
-        let sub_file: FileEntry = (&self.buffer[self.sub_files1 + FILE_ENTRY_SIZE * suboffset_index..])
-            .pread_with(0, LE).map_err(|x: ScrollError| GetFileError::InternalError(x.into()))?;
+        let len = (stored_size(&sub_file) as u64).min(self.file_len.saturating_sub(offset));
+        let data = self.read_at(offset, len as usize)?;
+
+        let mut sidecar_path = OsString::from(path.as_os_str());
+        sidecar_path.push(extension);
+        Ok(write_atomic(Path::new(&sidecar_path), self.fsync, |file| file.write_all(&data))?)
+    }
+
+    /// Extensions without their leading `.` keyed by their hash, guessed from `labels` for `ExtractOptions::unknown_dir` and `entries_with_unknown_ext`.
+    /// As it hasn't been confirmed whether the data.arc hashes extensions with their leading `.`, both forms are keyed.
+    fn extensions(&self, labels: &HashMap<u64, String>) -> HashMap<u64, String> {
+        let mut extensions = HashMap::new();
+        for label in labels.values() {
+            let name = label.rsplit('/').next().unwrap_or(label);
+            if let Some(dot) = name.rfind('.') {
+                let ext = &name[dot + 1..];
+                if !ext.is_empty() {
+                    extensions.insert(self.hasher.hash(ext), ext.to_string());
+                    extensions.insert(self.hasher.hash(&name[dot..]), ext.to_string());
+                }
+            }
+        }
+        // Labels that are just an extension, as in Hashes.txt
+        for (hash, label) in labels {
+            let ext = label.trim_start_matches('.');
+            if !ext.is_empty() && !ext.contains(['/', '.']) {
+                extensions.insert(*hash, ext.to_string());
+            }
+        }
+        extensions
+    }
+
+    /// When enabled, `extract_file`, `extract_by_hash` and `extract_all` sync each file to disk before renaming it into place.
+    /// This is slower but means a crash can't leave a file at its final path that is missing data. Disabled by default.
+    pub fn set_fsync(&mut self, fsync: bool) {
+        self.fsync = fsync;
+    }
 
-        let big_hash: BigHashEntry = read_big_hash_entry(&self.buffer[self.big_hashes + BIG_HASH_ENTRY_SIZE * tree.path.meta as usize ..]);
-        let big_file: BigFileEntry = (&self.buffer[self.big_files + BIG_FILE_ENTRY_SIZE * big_hash.path.meta as usize ..])
-            .pread_with(0, LE).map_err(|x: ScrollError| GetFileError::InternalError(x.into()))?;
+    /// When enabled, `get_file` also checks the hashes of the file name and extension of the passed path against the file's tree entry,
+    /// returning `GetFileError::FileNotFound` if they don't match.
+    /// The extension is accepted hashed with or without its leading `.`, as it hasn't been confirmed which of the two the data.arc uses.
+    /// Disabled by default, as the path hash alone is almost always enough.
+    pub fn set_strict_paths(&mut self, strict_paths: bool) {
+        self.strict_paths = strict_paths;
+    }
+
+    /// Set what `get_file` does when it encounters flag bits we don't know the meaning of.
+    pub fn set_unknown_flag_policy(&mut self, policy: UnknownFlagPolicy) {
+        self.unknown_flag_policy = policy;
+    }
+
+    fn check_unknown_flags(&self, hash: u64, tree: &TreeEntry, sub_file: &FileEntry) -> Result<(), GetFileError> {
+        let tree_flags = tree.unknown_flags();
+        let file_flags = sub_file.unknown_flags();
+        if tree_flags == 0 && file_flags == 0 {
+            return Ok(());
+        }
+
+        match self.unknown_flag_policy {
+            UnknownFlagPolicy::Ignore => Ok(()),
+            UnknownFlagPolicy::Warn => {
+                eprintln!("0x{:x} has unknown flags set: TreeEntry 0x{:08x}, FileEntry 0x{:08x}", hash, tree_flags, file_flags);
+                Ok(())
+            }
+            UnknownFlagPolicy::Error => {
+                Err(GetFileError::Unsupported(format!("Failed to extract 0x{:x}: Unknown flags set: TreeEntry 0x{:08x}, FileEntry 0x{:08x}", hash, tree_flags, file_flags)))
+            }
+        }
+    }
 
-        if sub_file.suboffset_decompressed() {
-            unimplemented!()
+    /// Returns the hashes of every tree entry whose data starts at `offset` from the start of the data.arc.
+    /// More than one hash is returned when multiple paths share the same data.
+    ///
+    /// This resolves every tree entry in the archive, entries that can't be resolved yet are skipped.
+    pub fn paths_at_offset(&self, offset: u64) -> Vec<u64> {
+        let mut hashes = vec!();
+        for i in 0..self.node_header.tree_count {
+            if let Ok(tree) = self.tree(TreeIndex(i)) {
+                let hash = tree.path.hash;
+                if let Ok(location) = self.resolve(tree) {
+                    if self.file_offset(&location.sub_file, &location.big_file).ok() == Some(offset) {
+                        hashes.push(hash);
+                    }
+                }
+            }
         }
+        hashes
+    }
 
-        if !sub_file.suboffset_compressed_zstd() {
-            return Err(GetFileError::InternalError(format_err!("Failed to extract {}: Unknown compression", file_name)));
+    /// Counts how many `FileEntry`s in `sub_files1` and `sub_files2` have each distinct flags value.
+    /// Useful for finding rare flag values that may indicate an encoding we don't handle yet.
+    pub fn flag_value_histogram(&self) -> BTreeMap<u32, usize> {
+        let count = self.node_header.sub_file_count() as usize;
+        let mut histogram = BTreeMap::new();
+        for i in 0..count {
+            // sub_files2 immediately follows sub_files1 so we can read both in one pass
+            let file_entry = slice_at(&self.buffer, self.sub_files1 + FILE_ENTRY_SIZE * i, FILE_ENTRY_SIZE).ok()
+                .and_then(|data| data.pread_with::<FileEntry>(0, LE).ok());
+            if let Some(file_entry) = file_entry {
+                *histogram.entry(file_entry.flags).or_insert(0) += 1;
+            }
         }
+        histogram
+    }
 
-        let mut buffer_comp = vec!(0; sub_file.comp_size as usize);
-        self.file.seek(SeekFrom::Start(self.header.file_section_offset + big_file.offset + sub_file.offset as u64 * 4))
-            .map_err(|x: IOError| GetFileError::InternalError(x.into()))?;
-        self.file.read_exact(&mut buffer_comp)
-            .map_err(|x: IOError| GetFileError::InternalError(x.into()))?;
+    /// The number of hash buckets used to lookup files, as read from the header bucket of the bucket table.
+    pub fn bucket_count(&self) -> u32 {
+        self.first_hash_bucket.num_entries
+    }
 
-        let mut buffer_decomp = vec!(0; sub_file.decomp_size as usize);
-        let bytes_copied = zstd::block::decompress_to_buffer(&buffer_comp, &mut buffer_decomp)
-            .map_err(|x: IOError| GetFileError::InternalError(x.into()))?;
-        if bytes_copied != sub_file.decomp_size as usize {
-            return Err(GetFileError::InternalError(format_err!("Failed to extract {}: Mismatch in expected and actual decompressed size", file_name)));
+    /// The average number of `file_lookup` entries in each hash bucket.
+    pub fn bucket_load_factor(&self) -> f64 {
+        let num_buckets = self.bucket_count();
+        if num_buckets == 0 {
+            return 0.0;
         }
+        self.node_header.file_lookup_count as f64 / num_buckets as f64
+    }
+
+    /// The number of `file_lookup` entries in the largest hash bucket.
+    /// This is the worst case number of entries that need to be searched to lookup a file.
+    pub fn max_bucket_len(&self) -> usize {
+        (0..self.bucket_count())
+            .filter_map(|i| self.bucket(i).ok())
+            .map(|bucket| bucket.num_entries as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns which big file the file's data is stored in.
+    /// The tree entry's `path.meta` indexes `big_hashes`, whose `path.meta` is in turn the index of the big file.
+    pub fn big_file_index(&mut self, hash: u64) -> Result<BigFileIndex, GetFileError> {
+        Ok(self.locate(hash)?.big_file_index)
+    }
+
+    /// For each big file containing data of the passed files, returns the range of absolute offsets covering that data.
+    /// This is the minimal set of byte ranges of the data.arc needed to extract the files.
+    ///
+    /// Files sharing a big file are merged into one range spanning all of them, which includes any data between them.
+    /// Files that share data, e.g. the same file under two paths, are only counted once.
+    pub fn required_big_files(&mut self, hashes: &[u64]) -> Result<Vec<(BigFileIndex, Range<u64>)>, GetFileError> {
+        let mut ranges: BTreeMap<BigFileIndex, Range<u64>> = BTreeMap::new();
+        for hash in hashes {
+            let location = self.locate(*hash)?;
+            let start = self.file_offset(&location.sub_file, &location.big_file)?;
+            let end = data_end(*hash, start, &location.sub_file)?;
+            ranges.entry(location.big_file_index)
+                .and_modify(|range| {
+                    range.start = range.start.min(start);
+                    range.end = range.end.max(end);
+                })
+                .or_insert(start..end);
+        }
+        Ok(ranges.into_iter().collect())
+    }
+
+    /// Iterate over the `EntryPair`s in the `file_lookup` table.
+    /// Each pair maps a path hash to the index of its tree entry, each bucket refers to a range of these sorted by hash.
+    pub fn file_lookup_entries(&self) -> impl Iterator<Item = EntryPair> + '_ {
+        (0..self.node_header.file_lookup_count as usize)
+            .filter_map(move |i| slice_at(&self.buffer, self.file_lookup + ENTRY_PAIR_SIZE * i, ENTRY_PAIR_SIZE).ok())
+            .map(read_pair)
+    }
+
+    /// Load paths to label hashes with, one path per line, as in the community maintained Hashes.txt.
+    /// Each path is hashed with the hasher set by `set_hasher`, `Hash40` by default.
+    /// Returns the number of paths loaded. Blank lines are skipped and `\r\n` line endings are handled.
+    ///
+    /// Labels are kept across calls, so multiple label files can be loaded.
+    pub fn load_labels(&mut self, reader: impl BufRead) -> io::Result<usize> {
+        let mut count = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let path = line.trim_end_matches(['\r', '\n']);
+            if path.trim().is_empty() {
+                continue;
+            }
+            let hash = self.hasher.hash(path);
+            self.labels.insert(hash, path.to_string());
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Build a tree of the folders and files in the archive from their labels, e.g. for a tree view.
+    ///
+    /// Paths come from `labels`, or from the labels loaded by `load_labels` if `labels` is None, and are split into folders on `/`.
+    /// Files without a label are placed directly in the root, named `0x<hash>`.
+    /// Folders are hashed with the hasher set by `set_hasher`, while files keep the hash they are stored under.
+    pub fn build_tree(&mut self, labels: Option<&HashMap<u64, String>>) -> PathNode {
+        let labels = labels.unwrap_or(&self.labels);
+        let paths: Vec<(u64, Option<String>)> = self.hashes().map(|hash| (hash, labels.get(&hash).cloned())).collect();
+
+        let mut root = FolderBuilder::default();
+        for (hash, path) in paths {
+            let path = path.unwrap_or_else(|| format!("0x{:x}", hash));
+            let mut components: Vec<&str> = path.split('/').filter(|component| !component.is_empty()).collect();
+            let name = components.pop().unwrap_or(&path).to_string();
+
+            let mut folder = &mut root;
+            for i in 0..components.len() {
+                let folder_hash = self.hasher.hash(&components[..=i].join("/"));
+                folder = &mut folder.folders.entry(components[i].to_string())
+                    .or_insert_with(|| (folder_hash, FolderBuilder::default())).1;
+            }
+            let metadata = self.file_metadata_by_hash(hash).ok();
+            folder.files.push(PathNode { name, hash, kind: PathNodeKind::File(metadata) });
+        }
+        root.build(String::new(), self.hasher.hash(""))
+    }
+
+    /// Every path loaded by `DataArc::load_labels`, keyed by hash.
+    pub fn labels(&self) -> &HashMap<u64, String> {
+        &self.labels
+    }
+
+    /// The path with the passed hash, if it was loaded by `DataArc::load_labels`.
+    pub fn name_for_hash(&self, hash: u64) -> Option<&str> {
+        self.labels.get(&hash).map(|name| name.as_str())
+    }
+
+    /// Iterate over the path hash of every file in the archive, in the order they are stored in the `file_lookup` table.
+    /// Any of them can be passed to `DataArc::get_file_by_hash` or `DataArc::extract_by_hash`.
+    pub fn hashes(&self) -> impl Iterator<Item = u64> + '_ {
+        self.file_lookup_entries().map(|pair| pair.hash)
+    }
+
+    /// Returns true if the archive contains a file with the passed path hash.
+    pub fn contains(&self, hash: u64) -> bool {
+        self.lookup_tree(hash).is_ok()
+    }
+
+    /// Returns the path hashes of every file whose file name, without its folder, has the passed hash.
+    /// The same file name can be in many folders, so there can be any number of matches.
+    pub fn find_by_file_hash(&self, file_hash: u64) -> Vec<u64> {
+        (0..self.node_header.tree_count)
+            .filter_map(|i| self.tree(TreeIndex(i)).ok())
+            .filter(|tree| tree.file.hash == file_hash)
+            .map(|tree| tree.path.hash)
+            .collect()
+    }
+
+    /// Returns the path hashes of every file whose extension hash isn't a known extension, e.g. to investigate them or pick a fallback extension.
+    ///
+    /// There is no built in list of extensions, so an extension is known if it is in the labels loaded by `load_labels`:
+    /// either a label that is just the extension, as Hashes.txt contains, or the extension of any labeled path.
+    /// An extension hash of 0 is never known. With no labels loaded every file is returned.
+    pub fn entries_with_unknown_ext(&self) -> Vec<u64> {
+        let extensions = self.extensions(&self.labels);
+        (0..self.node_header.tree_count)
+            .filter_map(|i| self.tree(TreeIndex(i)).ok())
+            .filter(|tree| tree.ext.hash == 0 || !extensions.contains_key(&tree.ext.hash))
+            .map(|tree| tree.path.hash)
+            .collect()
+    }
+
+    /// Returns the tree entry the path hash resolves to, or None if no file has that hash.
+    pub fn tree_entry(&self, hash: u64) -> Option<TreeEntry> {
+        self.lookup_tree(hash).ok()
+    }
+
+    /// Write how every tree entry resolves to its data, for debugging archives where files resolve wrong.
+    ///
+    /// One line is written per tree entry, in the order of the trees table, consisting of space separated `key=value` fields:
+    /// *   `tree`: index of the tree entry, `hash`: its path hash, `flags`: its flags
+    /// *   `bucket`: the bucket the hash selects and `entry`: the tree index found in that bucket's `file_lookup` pairs
+    /// *   `big_hash`: index into `big_hashes`, `big_file`: index into `big_files` and `big_file_offset`: its offset
+    /// *   `sub_file.offset`, `sub_file.comp_size`, `sub_file.decomp_size` and `sub_file.flags`: the fields of the resolved `FileEntry`
+    /// *   `offset`: the absolute offset of the data within the data.arc
+    ///
+    /// When a step can't be resolved, the line ends with an `error` field containing the error instead.
+    pub fn trace_all(&self, writer: &mut impl Write) -> io::Result<()> {
+        for i in 0..self.node_header.tree_count {
+            let mut line = format!("tree={}", i);
+            if let Err(err) = self.trace(&mut line, TreeIndex(i)) {
+                line.push_str(&format!(" error={:?}", err));
+            }
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Append the fields of a single `trace_all` line following the tree index, stopping at the first step that fails.
+    fn trace(&self, line: &mut String, index: TreeIndex) -> Result<(), GetFileError> {
+        let tree = self.tree(index)?;
+        let hash = tree.path.hash;
+        line.push_str(&format!(" hash=0x{:x} flags=0x{:08x}", hash, tree.flags));
+
+        let bucket_index = self.bucket_index(hash)?;
+        let bucket = self.bucket(bucket_index)?;
+        line.push_str(&format!(" bucket={}", bucket_index));
+        match self.bucket_search(hash, &bucket) {
+            Ok(entry) => line.push_str(&format!(" entry={}", entry.meta)),
+            Err(_) => line.push_str(" entry=none"),
+        }
+
+        let big_hash = tree.path.meta;
+        let location = self.resolve(tree)?;
+        let sub_file = &location.sub_file;
+        line.push_str(&format!(" big_hash={} big_file={} big_file_offset=0x{:x}", big_hash, location.big_file_index.0, location.big_file.offset));
+        line.push_str(&format!(" sub_file.offset=0x{:x} sub_file.comp_size=0x{:x} sub_file.decomp_size=0x{:x} sub_file.flags=0x{:08x}",
+            sub_file.offset, sub_file.comp_size, sub_file.decomp_size, sub_file.flags));
+        line.push_str(&format!(" offset=0x{:x}", self.file_offset(sub_file, &location.big_file)?));
+        Ok(())
+    }
+
+    /// Lists the path hash of every tree entry that can't be extracted, along with the reason why.
+    /// No file data is read, so this is much faster than attempting to extract everything.
+    pub fn problem_files(&self) -> Vec<(u64, ProblemKind)> {
+        let mut problems = vec!();
+        for i in 0..self.node_header.tree_count {
+            let tree = match self.tree(TreeIndex(i)) {
+                Ok(tree) => tree,
+                Err(_) => continue,
+            };
+            let hash = tree.path.hash;
+
+            let problem = match self.follow_redirects(tree) {
+                Err(_) => Some(ProblemKind::Redirect),
+                Ok(tree) => match self.resolve(tree) {
+                    Ok(location) => {
+                        let sub_file = &location.sub_file;
+                        let end = self.file_offset(sub_file, &location.big_file)
+                            .and_then(|offset| data_end(hash, offset, sub_file));
+                        if compression(sub_file) == Compression::Unknown {
+                            Some(ProblemKind::UnknownCompression)
+                        } else if end.map(|end| end > self.file_len).unwrap_or(true) {
+                            Some(ProblemKind::OutOfRange)
+                        } else {
+                            None
+                        }
+                    }
+                    // Once redirects are followed sub file redirection is the only unimplemented case,
+                    // so resolving can otherwise only fail on an out of range index
+                    Err(GetFileError::Unsupported(_)) => Some(ProblemKind::SubFileRedirect),
+                    Err(_) => Some(ProblemKind::OutOfRange),
+                },
+            };
+
+            if let Some(problem) = problem {
+                problems.push((hash, problem));
+            }
+        }
+        problems
+    }
+
+    /// Compare the total compressed size of all files against the size of the file section.
+    pub fn packing_efficiency(&self) -> PackingReport {
+        let file_entry_count = self.node_header.sub_file_count() as usize;
+        let total_comp_size = (0..file_entry_count)
+            .filter_map(|i| slice_at(&self.buffer, self.sub_files1 + FILE_ENTRY_SIZE * i, FILE_ENTRY_SIZE).ok())
+            .filter_map(|data| data.pread_with::<FileEntry>(0, LE).ok())
+            .map(|file_entry| file_entry.comp_size as u64)
+            .sum();
+
+        PackingReport {
+            file_section_size: self.section_end(self.header.file_section_offset).saturating_sub(self.header.file_section_offset),
+            file_entry_count,
+            total_comp_size,
+        }
+    }
+
+    /// Size of the music section in bytes.
+    /// The header only stores section offsets, so this is the distance to the next section, or to the end of the data.arc if it is the last section.
+    pub fn music_section_size(&self) -> u64 {
+        self.section_end(self.header.music_section_offset).saturating_sub(self.header.music_section_offset)
+    }
+
+    /// Size of the music file section in bytes, which contains the streamed audio data.
+    /// The header only stores section offsets, so this is the distance to the next section, or to the end of the data.arc if it is the last section.
+    pub fn music_file_section_size(&self) -> u64 {
+        self.section_end(self.header.music_file_section_offset).saturating_sub(self.header.music_file_section_offset)
+    }
+
+    /// The header only stores where each section starts, so a section ends where the next section starts or at the end of the data.arc.
+    fn section_end(&self, section_offset: u64) -> u64 {
+        let header = &self.header;
+        [header.music_file_section_offset, header.file_section_offset, header.music_section_offset, header.node_section_offset, header.unk_section_offset]
+            .iter()
+            .cloned()
+            .filter(|offset| *offset > section_offset)
+            .min()
+            .unwrap_or(self.file_len)
+    }
 
-        Ok(buffer_decomp)
+    /// Returns the path hashes in each hash bucket, indexed by bucket.
+    /// The hashes within a bucket are in the order they are stored in `file_lookup`, which should be sorted by hash.
+    pub fn entries_by_bucket(&self) -> Vec<Vec<u64>> {
+        (0..self.bucket_count())
+            .map(|i| match self.bucket(i) {
+                Ok(bucket) => (0..bucket.num_entries as usize)
+                    .filter_map(|j| self.node_slice(self.file_lookup + ENTRY_PAIR_SIZE * (bucket.index as usize + j), ENTRY_PAIR_SIZE).ok())
+                    .map(|data| read_pair(data).hash)
+                    .collect(),
+                Err(_) => vec!(),
+            })
+            .collect()
     }
 
-    /// TODO: Binary search
+    /// Find files with identical decompressed contents, including how many bytes are wasted by them not sharing data.
+    ///
+    /// This extracts every file in the archive so it is very slow.
+    /// Contents are compared by a 64 bit hash of the decompressed data, so a hash collision could cause a false match, though it is very unlikely.
+    pub fn content_dedup(&mut self) -> DedupReport {
+        // content hash -> the files with those contents
+        let mut contents: HashMap<u64, DedupCandidate> = HashMap::new();
+        let mut failed = vec!();
+
+        for i in 0..self.node_header.tree_count {
+            let hash = match self.tree(TreeIndex(i)) {
+                Ok(tree) => tree.path.hash,
+                Err(_) => continue,
+            };
+            let result = self.get_file_by_hash(hash).and_then(|data| {
+                let location = self.locate(hash)?;
+                let offset = self.file_offset(&location.sub_file, &location.big_file)?;
+                Ok((data, location, offset))
+            });
+            let (data, location, offset) = match result {
+                Ok(result) => result,
+                Err(_) => {
+                    failed.push(hash);
+                    continue;
+                }
+            };
+
+            let mut hasher = DefaultHasher::new();
+            data.hash(&mut hasher);
+
+            let candidate = contents.entry(hasher.finish()).or_insert_with(|| DedupCandidate {
+                decomp_size: data.len() as u64,
+                hashes: vec!(),
+                copies: BTreeMap::new(),
+            });
+            candidate.hashes.push(hash);
+            candidate.copies.insert(offset, location.sub_file.comp_size as u64);
+        }
+
+        let mut groups: Vec<DedupGroup> = contents.into_values()
+            .filter(|candidate| candidate.hashes.len() > 1)
+            .map(|candidate| DedupGroup {
+                hashes: candidate.hashes,
+                decomp_size: candidate.decomp_size,
+                copy_comp_sizes: candidate.copies.values().cloned().collect(),
+            })
+            .collect();
+        groups.sort_by_key(|group| group.hashes[0]);
+
+        DedupReport { groups, failed }
+    }
+
+    /// Returns the path hashes of every tree entry whose data doesn't start at a multiple of `align` bytes from the start of the data.arc.
+    /// Misaligned files can indicate a bug in the resolution logic.
+    ///
+    /// Offsets within a big file are stored in units of 4 bytes, so files are expected to be at least 4 byte aligned
+    /// relative to their big file. The alignment of big files themselves is not known.
+    /// Entries that can't be resolved yet are skipped.
+    pub fn check_alignment(&self, align: u64) -> Vec<u64> {
+        let align = align.max(1);
+        let mut hashes = vec!();
+        for i in 0..self.node_header.tree_count {
+            if let Ok(tree) = self.tree(TreeIndex(i)) {
+                let hash = tree.path.hash;
+                if let Ok(location) = self.resolve(tree) {
+                    if self.file_offset(&location.sub_file, &location.big_file).is_ok_and(|offset| !offset.is_multiple_of(align)) {
+                        hashes.push(hash);
+                    }
+                }
+            }
+        }
+        hashes
+    }
+
+    /// Find the tree entry for the passed path hash via the hash buckets.
+    fn lookup_tree(&self, hash: u64) -> Result<TreeEntry, GetFileError> {
+        let bucket = self.bucket(self.bucket_index(hash)?)?;
+        let entry = self.bucket_search(hash, &bucket)?;
+        let tree = self.tree(TreeIndex(entry.meta))?;
+        // The tree stores the full path hash too, if it doesn't match then the lookup tables are inconsistent
+        // and the tree is for some other file, so returning its data would be wrong.
+        if tree.path.hash != hash {
+            return Err(GetFileError::FileNotFound);
+        }
+        Ok(tree)
+    }
+
+    /// Check that the hashes of the file name and extension of `file_name` match the tree entry of `hash`, for `set_strict_paths`.
+    /// This catches a different path whose hash collides with `file_name`, as it is very unlikely to collide on every component too.
+    fn check_path_components(&self, file_name: &str, hash: u64) -> Result<(), GetFileError> {
+        let tree = self.lookup_tree(hash)?;
+        let name = file_name.rsplit('/').next().unwrap_or(file_name);
+        if tree.file.hash != self.hasher.hash(name) {
+            return Err(GetFileError::FileNotFound);
+        }
+        // Whether the extension is hashed with its leading `.` hasn't been confirmed against a retail data.arc, so either is accepted.
+        if let Some(dot) = name.rfind('.') {
+            if tree.ext.hash != self.hasher.hash(&name[dot + 1..]) && tree.ext.hash != self.hasher.hash(&name[dot..]) {
+                return Err(GetFileError::FileNotFound);
+            }
+        }
+        Ok(())
+    }
+
+    /// The index of the bucket that the passed path hash is stored in, to be passed to `DataArc::bucket`.
+    ///
+    /// The bucket table is `bucket_count() + 1` HashBuckets long, but the first is the header bucket, which isn't part of the hash table.
+    /// So the modulo is by `bucket_count()` and `DataArc::bucket` adds 1 to the index to skip the header.
+    /// This gives every hash a valid bucket and never looks up the header, matching how `parse` sizes the table
+    /// and how `repack::build_hash_buckets` lays it out.
+    fn bucket_index(&self, hash: u64) -> Result<u32, GetFileError> {
+        let bucket_count = self.bucket_count();
+        if bucket_count == 0 {
+            return Err(GetFileError::Corrupt(format!("Failed to lookup 0x{:x}: The archive has no hash buckets", hash)));
+        }
+        Ok((hash % bucket_count as u64) as u32)
+    }
+
+    /// Read the bucket at `index`, skipping over the header bucket.
+    fn bucket(&self, index: u32) -> Result<HashBucket, GetFileError> {
+        let offset = self.file_lookup_buckets + HASH_BUCKET_SIZE * (index as usize + 1);
+        self.node_slice(offset, HASH_BUCKET_SIZE)?
+            .pread_with(0, LE).map_err(GetFileError::Scroll)
+    }
+
+    /// Follow the tree entry to the `FileEntry` and `BigFileEntry` that together locate its data.
+    /// The returned location's tree is the tree the data belongs to, i.e. the target of any redirects.
+    fn resolve(&self, tree: TreeEntry) -> Result<Location, GetFileError> {
+        let tree = self.follow_redirects(tree)?;
+
+        // The low two bits of the tree flags select where the index of the tree's sub file in `sub_files1` comes from:
+        // *   When both are clear, `tree.suboffset_index` is the index of the sub file itself.
+        //     It is checked against the size of `sub_files1` by `DataArc::sub_file`,
+        //     as an index past the end would silently read a FileEntry from whatever table follows.
+        // *   Otherwise `tree.ext.meta` is believed to be an index into `sub_files1` of a FileEntry that is either the sub file
+        //     or redirects to another sub file, depending on its flags. This case is not implemented.
+        //     The index is still checked, so that an out of range index is reported as corrupt rather than unsupported.
+        let suboffset_index = if tree.suboffset_index() {
+            SubFileIndex(tree.suboffset_index)
+        } else {
+            // TODO: Which FileEntry flag marks a redirect, and which field holds the offset of its target, isn't known yet.
+            // Until then guessing between the two cases would risk returning the wrong file's data.
+            check_index("sub_files1", tree.ext.meta, self.node_header.sub_files1_count)?;
+            return Err(GetFileError::Unsupported(format!("Failed to resolve 0x{:x}: Sub file redirection is not implemented", tree.path.hash)));
+        };
+        let sub_file = self.sub_file(suboffset_index)?;
+
+        check_index("big_hashes", tree.path.meta, self.node_header.folder_count)?;
+        let big_hash: BigHashEntry = read_big_hash_entry(self.node_slice(self.big_hashes + BIG_HASH_ENTRY_SIZE * tree.path.meta as usize, BIG_HASH_ENTRY_SIZE)?);
+        let big_file_index = BigFileIndex(big_hash.path.meta);
+        let big_file = self.big_file(big_file_index)?;
+
+        Ok(Location { tree, sub_file, big_file, big_file_index })
+    }
+
+    /// A redirect tree entry is an alias of another file: its `suboffset_index` is the index of the target tree entry in `trees`.
+    /// Returns the first tree entry that isn't a redirect, failing if there are more than `MAX_REDIRECT_DEPTH` redirects,
+    /// as any chain that long is most likely a cycle.
+    fn follow_redirects(&self, mut tree: TreeEntry) -> Result<TreeEntry, GetFileError> {
+        let hash = tree.path.hash;
+        let mut depth = 0;
+        while tree.redirect() {
+            if depth == MAX_REDIRECT_DEPTH {
+                return Err(GetFileError::Corrupt(format!("Failed to resolve 0x{:x}: More than {} redirects", hash, MAX_REDIRECT_DEPTH)));
+            }
+            tree = self.tree(TreeIndex(tree.suboffset_index))?;
+            depth += 1;
+        }
+        Ok(tree)
+    }
+
+    /// The absolute offset of the sub file's data within the data.arc.
+    /// The offsets come straight from the archive, so a corrupt big file offset can overflow.
+    fn file_offset(&self, sub_file: &FileEntry, big_file: &BigFileEntry) -> Result<u64, GetFileError> {
+        self.header.file_section_offset.checked_add(big_file.offset)
+            .and_then(|offset| offset.checked_add(sub_file.offset as u64 * 4))
+            .ok_or_else(|| GetFileError::Corrupt(format!(
+                "The sub file offset 0x{:x} * 4 + big file offset 0x{:x} + file section offset 0x{:x} overflows",
+                sub_file.offset, big_file.offset, self.header.file_section_offset
+            )))
+    }
+
+    fn node_slice(&self, offset: usize, len: usize) -> Result<&[u8], GetFileError> {
+        slice_at(&self.buffer, offset, len).map_err(GetFileError::Corrupt)
+    }
+
+    fn tree(&self, index: TreeIndex) -> Result<TreeEntry, GetFileError> {
+        check_index("trees", index.0, self.node_header.tree_count)?;
+        Ok(read_tree_entry(self.node_slice(self.trees + TREE_ENTRY_SIZE * index.0 as usize, TREE_ENTRY_SIZE)?))
+    }
+
+    fn sub_file(&self, index: SubFileIndex) -> Result<FileEntry, GetFileError> {
+        check_index("sub_files1", index.0, self.node_header.sub_files1_count)?;
+        self.node_slice(self.sub_files1 + FILE_ENTRY_SIZE * index.0 as usize, FILE_ENTRY_SIZE)?
+            .pread_with(0, LE).map_err(GetFileError::Scroll)
+    }
+
+    fn big_file(&self, index: BigFileIndex) -> Result<BigFileEntry, GetFileError> {
+        check_index("big_files", index.0, self.node_header.big_file_count())?;
+        self.node_slice(self.big_files + BIG_FILE_ENTRY_SIZE * index.0 as usize, BIG_FILE_ENTRY_SIZE)?
+            .pread_with(0, LE).map_err(GetFileError::Scroll)
+    }
+
+    /// Find the entry for the passed hash among the bucket's `num_entries` entries starting at `bucket.index` in `file_lookup`.
+    /// The entries within a bucket are sorted by hash so they can be binary searched.
     fn bucket_search(&self, hash: u64, bucket: &HashBucket) -> Result<EntryPair, GetFileError> {
         let start_index = self.file_lookup + ENTRY_PAIR_SIZE * bucket.index as usize;
-        for i in 0..self.first_hash_bucket.num_entries as usize {
-            let pair = read_pair(&self.buffer[start_index + ENTRY_PAIR_SIZE * i ..]);
-            if pair.hash == hash {
-                return Ok(pair);
-            }
+        let data = self.node_slice(start_index, ENTRY_PAIR_SIZE * bucket.num_entries as usize)?;
+        let (entries, _) = data.as_chunks::<ENTRY_PAIR_SIZE>();
+        let i = entries.partition_point(|entry| read_pair(entry).hash < hash);
+        match entries.get(i).map(|entry| read_pair(entry)) {
+            Some(entry) if entry.hash == hash => Ok(entry),
+            _ => Err(GetFileError::FileNotFound),
         }
-        Err(GetFileError::FileNotFound)
     }
 
-    pub fn debug_print(&self) -> Result<(), Error> {
+    /// Read the first entry of a few key sections and check basic invariants on them.
+    /// This is much faster than reading every entry and is enough to catch an archive that has been grossly misparsed,
+    /// e.g. due to an unsupported version, but passing does not mean every entry is valid.
+    ///
+    /// The checks are:
+    /// *   `file_lookup_buckets`: There is at least one bucket, otherwise no hash can be looked up.
+    /// *   `file_lookup_buckets[1]`: The first bucket's range of entries lies within `file_lookup`.
+    /// *   `file_lookup`: The first entry's tree index is within `trees`.
+    /// *   `trees`: The first tree's `big_hashes` index is within `big_hashes`.
+    /// *   `big_hashes`: The first big hash's big file index is within `big_files`.
+    /// *   `big_files`: The first big file starts before the end of the data.arc.
+    /// *   `sub_files1`: The first sub file's compressed size is nonzero and no larger than the data.arc.
+    ///
+    /// Sections with no entries pass their check.
+    pub fn sanity_probe(&self) -> SanityProbe {
+        let header = &self.node_header;
+        let bucket_count = self.bucket_count();
+        let checks = vec!(
+            ("file_lookup_buckets", Ok(if bucket_count == 0 {
+                Err(String::from("The header bucket says there are 0 buckets"))
+            } else {
+                Ok(format!("{} buckets", bucket_count))
+            })),
+            ("file_lookup_buckets[1]", if bucket_count == 0 { Ok(Ok(String::from("empty"))) } else {
+                self.bucket(0).map(|bucket| {
+                    let end = bucket.index as u64 + bucket.num_entries as u64;
+                    in_range("entries end", end, header.file_lookup_count as u64 + 1)
+                })
+            }),
+            ("file_lookup", if header.file_lookup_count == 0 { Ok(Ok(String::from("empty"))) } else {
+                self.node_slice(self.file_lookup, ENTRY_PAIR_SIZE)
+                    .map(|data| in_range("tree index", read_pair(data).meta as u64, header.tree_count as u64))
+            }),
+            ("trees", if header.tree_count == 0 { Ok(Ok(String::from("empty"))) } else {
+                self.tree(TreeIndex(0))
+                    .map(|tree| in_range("big_hashes index", tree.path.meta as u64, header.folder_count as u64))
+            }),
+            ("big_hashes", if header.folder_count == 0 { Ok(Ok(String::from("empty"))) } else {
+                self.node_slice(self.big_hashes, BIG_HASH_ENTRY_SIZE).map(|data| {
+                    let big_hash = read_big_hash_entry(data);
+                    in_range("big_files index", big_hash.path.meta as u64, header.big_file_count())
+                })
+            }),
+            ("big_files", if header.big_file_count() == 0 { Ok(Ok(String::from("empty"))) } else {
+                self.big_file(BigFileIndex(0)).map(|big_file| {
+                    let offset = self.header.file_section_offset.saturating_add(big_file.offset);
+                    in_range("offset", offset, self.file_len)
+                })
+            }),
+            ("sub_files1", if header.sub_files1_count == 0 { Ok(Ok(String::from("empty"))) } else {
+                self.sub_file(SubFileIndex(0)).map(|sub_file| {
+                    if sub_file.comp_size == 0 {
+                        Err(String::from("compressed size is 0"))
+                    } else {
+                        in_range("compressed size", sub_file.comp_size as u64, self.file_len + 1)
+                    }
+                })
+            }),
+        );
+
+        let checks = checks.into_iter().map(|(section, result)| {
+            let (passed, details) = match result {
+                Ok(Ok(details)) => (true, details),
+                Ok(Err(details)) => (false, details),
+                Err(err) => (false, format!("Failed to read the first entry: {}", err)),
+            };
+            SanityCheck { section, passed, details }
+        }).collect();
+        SanityProbe { checks }
+    }
+
+    /// Read the first entry of each table in the node section.
+    /// Useful for inspecting the raw entries when working out the format of a new data.arc.
+    pub fn sections(&self) -> Result<ArcSections, ParseError> {
+        let slice = |offset, len| slice_at(&self.buffer, offset, len).map_err(ParseError::Corrupt);
+        Ok(ArcSections {
+            bulkfile_category_info: read_triplet(slice(0, ENTRY_TRIPLET_SIZE)?),
+            bulkfile_hash_lookup: read_pair(slice(self.bulkfile_hash_lookup, ENTRY_PAIR_SIZE)?),
+            bulkfiles_by_name: read_triplet(slice(self.bulkfiles_by_name, ENTRY_TRIPLET_SIZE)?),
+            bulkfile_lookup_to_fileidx: LittleEndian::read_u32(slice(self.bulkfile_lookup_to_fileidx, 4)?),
+            file_pairs: slice(self.file_pairs, FILE_PAIR_SIZE)?.pread_with(0, LE)?,
+            another_hash_table: read_triplet(slice(self.another_hash_table, ENTRY_TRIPLET_SIZE)?),
+            big_hashes: read_big_hash_entry(slice(self.big_hashes, BIG_HASH_ENTRY_SIZE)?),
+            big_files: slice(self.big_files, BIG_FILE_ENTRY_SIZE)?.pread_with(0, LE)?,
+            folder_hash_lookup: read_pair(slice(self.folder_hash_lookup, ENTRY_PAIR_SIZE)?),
+            trees: read_tree_entry(slice(self.trees, TREE_ENTRY_SIZE)?),
+            sub_files1: slice(self.sub_files1, FILE_ENTRY_SIZE)?.pread_with(0, LE)?,
+            sub_files2: slice(self.sub_files2, FILE_ENTRY_SIZE)?.pread_with(0, LE)?,
+            folder_to_big_hash: read_pair(slice(self.folder_to_big_hash, ENTRY_PAIR_SIZE)?),
+            file_lookup_buckets: slice(self.file_lookup_buckets, HASH_BUCKET_SIZE)?.pread_with(0, LE)?,
+            file_lookup: read_pair(slice(self.file_lookup, ENTRY_PAIR_SIZE)?),
+            numbers: read_pair(slice(self.numbers, ENTRY_PAIR_SIZE)?),
+        })
+    }
+
+    /// Print the first entry of each table in the node section, see `DataArc::sections`.
+    pub fn debug_print(&self) -> Result<(), ParseError> {
         // TODO: print all elements
-        println!("bulkfile_category_info: {:x?}", read_triplet(&self.buffer[..]));
-        println!("bulkfile_hash_lookup: {:x?}", read_pair(&self.buffer[self.bulkfile_hash_lookup..]));
-        println!("bulkfiles_by_name: {:x?}", read_triplet(&self.buffer[self.bulkfiles_by_name..]));
-        println!("bulkfile_lookup_tofileidx: {:x?}", LittleEndian::read_u32(&self.buffer[self.bulkfile_lookup_to_fileidx..]));
-        let file_pair: FilePair = (&self.buffer[self.file_pairs..]).pread_with(0, LE)?;
-        println!("file_pairs: {:x?}", file_pair);
-        println!("another_hash_table: {:x?}", read_triplet(&self.buffer[self.another_hash_table..]));
-        println!("big_hashes: {:x?}", read_big_hash_entry(&self.buffer[self.big_hashes..]));
-        let big_file: BigFileEntry = (&self.buffer[self.big_files..]).pread_with(0, LE)?;
-        println!("big_files: {:x?}", big_file);
-        println!("folder_hash_lookup: {:x?}", read_pair(&self.buffer[self.folder_hash_lookup..]));
-        println!("trees: {:x?}", read_tree_entry(&self.buffer[self.trees..]));
-        let file_entry: FileEntry = (&self.buffer[self.sub_files1..]).pread_with(0, LE)?;
-        println!("sub_files1: {:x?}", file_entry);
-        let file_entry: FileEntry = (&self.buffer[self.sub_files2..]).pread_with(0, LE)?;
-        println!("sub_files2: {:x?}", file_entry);
-        println!("folder_to_big_hash: {:x?}", read_pair(&self.buffer[self.folder_to_big_hash..]));
-        let hash_bucket: HashBucket = (&self.buffer[self.file_lookup_buckets..]).pread_with(0, LE)?;
-        println!("file_lookup_buckets: {:x?}", hash_bucket);
-        println!("file_lookup: {:x?}", read_pair(&self.buffer[self.file_lookup..]));
-        println!("numbers: {:x?}", read_pair(&self.buffer[self.numbers..]));
+        println!("{:#x?}", self.sections()?);
+        Ok(())
+    }
+}
+
+/// Extract every file in the data.arc at `archive_path` into `out_dir`, the quickest way to dump an archive.
+///
+/// If `labels_path` is set, the paths listed in it (one per line, as in Hashes.txt) are loaded with `DataArc::load_labels` to name the files.
+/// Otherwise, and for any file missing from the labels, files are written to `0x<hash>.bin`.
+/// Everything else is left at its default, in particular files are hashed with `Hash40`, unknown flags are ignored
+/// and `ExtractOptions::default()` is used. For anything else open the data.arc and call `DataArc::extract_all` directly.
+///
+/// As with `extract_all`, files failing to extract are recorded in the returned report rather than stopping the extraction.
+pub fn extract(archive_path: impl AsRef<Path>, out_dir: impl AsRef<Path>, labels_path: Option<&Path>) -> Result<ExtractReport, ExtractError> {
+    let mut data_arc = DataArc::from_path(archive_path)?;
+    if let Some(labels_path) = labels_path {
+        let labels = File::open(labels_path).map_err(ExtractError::Labels)?;
+        data_arc.load_labels(BufReader::new(labels)).map_err(ExtractError::Labels)?;
+    }
+    Ok(data_arc.extract_all(out_dir.as_ref(), None, &ExtractOptions::default())?)
+}
+
+/// The entries that together locate a file's data
+#[derive(Clone)]
+struct Location {
+    tree: TreeEntry,
+    sub_file: FileEntry,
+    big_file: BigFileEntry,
+    big_file_index: BigFileIndex,
+}
+
+/// The `len` bytes of the memory map starting at `offset`, or an `ErrorKind::UnexpectedEof` error if that is past its end,
+/// matching the error from reading past the end of the file.
+#[cfg(feature = "mmap")]
+fn mmap_slice(mmap: &memmap2::Mmap, offset: u64, len: u64) -> io::Result<&[u8]> {
+    offset.checked_add(len)
+        .filter(|end| *end <= mmap.len() as u64)
+        .map(|end| &mmap[offset as usize..end as usize])
+        .ok_or_else(|| IOError::new(ErrorKind::UnexpectedEof, format!(
+            "Tried to read 0x{:x} bytes at 0x{:x} but the data.arc is only 0x{:x} bytes long", len, offset, mmap.len()
+        )))
+}
+
+/// Seek `file`, which is at `position` if known, to `offset`. `position` is left as None.
+fn seek_buffered(file: &mut BufReader<impl Read + Seek>, position: &mut Option<u64>, offset: u64) -> io::Result<()> {
+    // `BufReader::seek` always discards the buffer, while `seek_relative` keeps it if the target is already buffered.
+    // When our position is unknown, e.g. after reading through `raw_reader`, `stream_position` finds it without discarding the buffer.
+    let position = position.take().or_else(|| file.stream_position().ok());
+    let relative = position.and_then(|position| i64::try_from(offset as i128 - position as i128).ok());
+    match relative {
+        Some(relative) => file.seek_relative(relative)?,
+        None => { file.seek(SeekFrom::Start(offset))?; }
+    }
+    Ok(())
+}
+
+/// Copy everything from `reader` to `writer`, returning the number of bytes copied.
+/// Unlike `io::copy` read and write errors are kept apart: read errors are converted with `read_error`, write errors are `GetFileError::Io`.
+fn copy_all(mut reader: impl Read, writer: &mut impl Write, read_error: fn(IOError) -> GetFileError) -> Result<u64, GetFileError> {
+    let mut buffer = [0; 0x10000];
+    let mut total = 0;
+    loop {
+        let len = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(total),
+            Ok(len) => len,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(read_error(err)),
+        };
+        writer.write_all(&buffer[..len])?;
+        total += len as u64;
+    }
+}
+
+/// Create the directory that `path` is in, if it has one.
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+/// Hard link `path` to `original`, replacing any existing file at `path`.
+fn hard_link(original: &Path, path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+        _ => { }
+    }
+    fs::hard_link(original, path)
+}
 
+/// Call `write` on a file at `path` with `.part` appended and then rename it to `path`.
+/// If `write` fails the `.part` file is removed, so nothing is left at either path.
+fn write_atomic(path: &Path, fsync: bool, write: impl FnOnce(&mut File) -> Result<(), IOError>) -> Result<(), IOError> {
+    let mut part_path = OsString::from(path.as_os_str());
+    part_path.push(".part");
+    let part_path = PathBuf::from(part_path);
+
+    let result = File::create(&part_path).and_then(|mut file| {
+        write(&mut file)?;
+        if fsync {
+            file.sync_all()?;
+        }
         Ok(())
+    });
+
+    match result {
+        Ok(()) => fs::rename(&part_path, path),
+        Err(err) => {
+            fs::remove_file(&part_path).ok();
+            Err(err)
+        }
+    }
+}
+
+/// The offset just past the end of the sub file's data, which starts at `offset`
+fn data_end(hash: u64, offset: u64, sub_file: &FileEntry) -> Result<u64, GetFileError> {
+    let size = stored_size(sub_file);
+    offset.checked_add(size as u64)
+        .ok_or_else(|| GetFileError::Corrupt(format!("The 0x{:x} bytes of 0x{:x} at 0x{:x} overflow", size, hash, offset)))
+}
+
+/// The number of bytes the sub file's data takes in the data.arc.
+/// Uncompressed data is read using `decomp_size`, so that is used for it rather than `comp_size`.
+fn stored_size(sub_file: &FileEntry) -> u32 {
+    match compression(sub_file) {
+        Compression::None => sub_file.decomp_size,
+        _ => sub_file.comp_size,
+    }
+}
+
+/// The compression the sub file's flags say its data is stored with
+fn compression(sub_file: &FileEntry) -> Compression {
+    if sub_file.suboffset_decompressed() {
+        Compression::None
+    } else if sub_file.suboffset_compressed_zstd() {
+        Compression::Zstd
+    } else {
+        Compression::Unknown
     }
 }
 
-fn hash40(name: &str) -> u64 {
-    crc::crc32::checksum_ieee(name.as_bytes()) as u64 | ((name.len() as u64 & 0xFF) << 32)
+/// Describes whether `value` is less than `limit`, for `DataArc::sanity_probe`.
+fn in_range(name: &str, value: u64, limit: u64) -> Result<String, String> {
+    if value < limit {
+        Ok(format!("{} 0x{:x} is less than 0x{:x}", name, value, limit))
+    } else {
+        Err(format!("{} 0x{:x} is not less than 0x{:x}", name, value, limit))
+    }
+}
+
+/// Returns an error if `index` is past the end of a table with `count` entries.
+fn check_index(table: &str, index: u32, count: impl Into<u64>) -> Result<(), GetFileError> {
+    let count = count.into();
+    if index as u64 >= count {
+        return Err(GetFileError::Corrupt(format!("Index {} is out of range of the {} table which has {} entries", index, table, count)));
+    }
+    Ok(())
+}
+
+/// Read the NodeHeader and the node tables following it from the node section at `node_section_offset`.
+/// If `compressed` is set the node section is a CompressedNodeHeader followed by the zstd compressed NodeHeader and node tables,
+/// otherwise the NodeHeader is stored directly at `node_section_offset`.
+/// Either way the returned buffer starts at the first node table, so all offsets into it are the same.
+///
+/// A compressed node section may be split into multiple concatenated zstd frames, e.g. by a packer limiting the size of each frame.
+/// `zstd::block::decompress_to_buffer` keeps decompressing frames until the compressed data runs out,
+/// so every frame is decompressed into the buffer one after the other, and the total size is then checked against `decomp_size`.
+fn read_node(file: &mut (impl Read + Seek), node_section_offset: u64, compressed: Option<&CompressedNodeHeader>) -> Result<(NodeHeader, Vec<u8>), ParseError> {
+    if let Some(compressed) = compressed {
+        let offset = node_section_offset + compressed.data_start as u64;
+        let buffer_comp = read_section(file, offset, compressed.zstd_comp_size as usize, "compressed node section")?;
+        let mut buffer = vec!(0; compressed.decomp_size as usize);
+        let bytes_copied = zstd::block::decompress_to_buffer(&buffer_comp, &mut buffer)
+            .map_err(|x| ParseError::Decompress(offset_error("compressed node section", offset, x)))?;
+        if bytes_copied != buffer.len() {
+            return Err(ParseError::SizeMismatch { expected: buffer.len() as u64, actual: bytes_copied as u64 });
+        }
+
+        let node_header: NodeHeader = buffer.pread_with(0, LE)?;
+        buffer.drain(..NODE_HEADER_SIZE);
+        Ok((node_header, buffer))
+    } else {
+        let buffer = read_section(file, node_section_offset, NODE_HEADER_SIZE, "NodeHeader")?;
+        let node_header: NodeHeader = buffer.pread_with(0, LE)?;
+
+        let node_size = (node_header.file_size as usize).checked_sub(NODE_HEADER_SIZE)
+            .ok_or_else(|| ParseError::Corrupt(format!("The node section size 0x{:x} is smaller than the node header", node_header.file_size)))?;
+        let buffer = read_section(file, node_section_offset + NODE_HEADER_SIZE as u64, node_size, "node tables")?;
+        Ok((node_header, buffer))
+    }
+}
+
+/// Read `len` bytes at `offset` from the start of the file.
+/// On failure the error says what was being read and where, so that reports of corrupt archives point at the corruption.
+fn read_section(file: &mut (impl Read + Seek), offset: u64, len: usize, what: &str) -> io::Result<Vec<u8>> {
+    let mut buffer = vec!(0; len);
+    file.seek(SeekFrom::Start(offset))
+        .and_then(|_| file.read_exact(&mut buffer))
+        .map_err(|x| offset_error(what, offset, x))?;
+    Ok(buffer)
+}
+
+/// Add what was being read and its offset from the start of the file to the message of `err`, keeping its kind.
+fn offset_error(what: &str, offset: u64, err: IOError) -> IOError {
+    IOError::new(err.kind(), format!("Failed reading {} at file offset 0x{:x}: {}", what, offset, err))
+}
+
+/// Returns an error naming the first section whose end lies past the end of the node section.
+/// `sections` contains the name of each section and the offset it ends at, in the order they are laid out.
+fn check_section_ends(buffer: &[u8], sections: &[(&str, usize)]) -> Result<(), ParseError> {
+    for (name, end) in sections {
+        if *end > buffer.len() {
+            return Err(ParseError::Corrupt(format!(
+                "The {} section ends at 0x{:x} but the node section is only 0x{:x} bytes long. The archive may contain padding between sections.",
+                name, end, buffer.len()
+            )));
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,54 @@
+use std::fs::File;
+use std::io::{Read, Seek};
+
+use crate::{DataArc, GetFileError};
+
+/// Multiple `DataArc`s layered on top of each other, like the game loading a patch archive over the base archive.
+///
+/// The layers are in priority order, the first layer has the highest priority.
+/// A file in a layer shadows the files with the same hash in all lower priority layers.
+pub struct OverlayArc<R = File> {
+    layers: Vec<DataArc<R>>,
+}
+
+impl<R: Read + Seek> OverlayArc<R> {
+    /// Create an overlay of `layers`, highest priority first.
+    pub fn new(layers: Vec<DataArc<R>>) -> OverlayArc<R> {
+        OverlayArc { layers }
+    }
+
+    /// The layers, highest priority first.
+    pub fn layers(&self) -> &[DataArc<R>] {
+        &self.layers
+    }
+
+    /// Returns true if any layer contains a file with the passed hash.
+    pub fn contains(&self, hash: u64) -> bool {
+        self.layers.iter().any(|layer| layer.contains(hash))
+    }
+
+    /// Extract the file from the highest priority layer containing it.
+    /// The file name is hashed separately by each layer's hasher.
+    ///
+    /// If the highest priority layer containing the file fails to extract it, that error is returned rather than trying lower layers.
+    pub fn get_file(&mut self, file_name: &str) -> Result<Vec<u8>, GetFileError> {
+        for layer in &mut self.layers {
+            match layer.get_file(file_name) {
+                Err(GetFileError::FileNotFound) => { }
+                result => return result,
+            }
+        }
+        Err(GetFileError::FileNotFound)
+    }
+
+    /// Same as `get_file` but takes the hash40 of the file name.
+    pub fn get_file_by_hash(&mut self, hash: u64) -> Result<Vec<u8>, GetFileError> {
+        for layer in &mut self.layers {
+            match layer.get_file_by_hash(hash) {
+                Err(GetFileError::FileNotFound) => { }
+                result => return result,
+            }
+        }
+        Err(GetFileError::FileNotFound)
+    }
+}
@@ -1,7 +1,35 @@
 use byteorder::{LittleEndian, ByteOrder};
 use scroll_derive::Pread;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Pread)]
+/// Returns the `len` bytes of `buffer` starting at `offset`, or an error message if `buffer` is too short.
+///
+/// This is internal infrastructure: every fixed size read out of the node buffer goes through it,
+/// so that a truncated or corrupt archive results in an error rather than a panic.
+pub(crate) fn slice_at(buffer: &[u8], offset: usize, len: usize) -> Result<&[u8], String> {
+    offset.checked_add(len)
+        .and_then(|end| buffer.get(offset..end))
+        .ok_or_else(|| format!("Tried to read 0x{:x} bytes at 0x{:x} but the buffer is only 0x{:x} bytes long", len, offset, buffer.len()))
+}
+
+// The resolution chain indexes into several tables with plain integers taken from `meta` fields.
+// These newtypes keep the index spaces apart so an index into one table can't be used on another.
+
+/// Index into the `trees` section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct TreeIndex(pub u32);
+
+/// Index into the `sub_files1` section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct SubFileIndex(pub u32);
+
+/// Index of a big file: a group of files stored together in the file section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BigFileIndex(pub u32);
+
+#[derive(Debug, Clone, Pread)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) struct ArcHeader {
     pub music_file_section_offset: u64,
     pub file_section_offset: u64,
@@ -11,7 +39,8 @@ pub(crate) struct ArcHeader {
 }
 pub(crate) const ARC_HEADER_SIZE: usize = 0x28;
 
-#[derive(Debug, Pread)]
+#[derive(Debug, Clone, Pread)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) struct CompressedNodeHeader {
     pub data_start: u32,
     pub decomp_size: u32,
@@ -20,7 +49,28 @@ pub(crate) struct CompressedNodeHeader {
 }
 pub(crate) const COMPRESSED_NODE_HEADER_SIZE: usize = 0x10;
 
-#[derive(Debug, Pread)]
+/// The largest `decomp_size` a compressed node section may have relative to its `zstd_comp_size`.
+/// The node tables compress to roughly a third of their size, so anything near this is corrupt,
+/// and without a limit a tiny crafted file could make us allocate up to 4 GiB for the decompressed node section.
+pub(crate) const MAX_NODE_COMPRESSION_RATIO: u64 = 0x100;
+
+impl CompressedNodeHeader {
+    /// Returns true if the fields are consistent with a compressed node section that has `available` bytes until the end of the file.
+    pub fn is_consistent(&self, available: u64) -> bool {
+        self.data_start < 0x100
+            && self.data_start as usize >= COMPRESSED_NODE_HEADER_SIZE
+            && self.decomp_size as usize >= NODE_HEADER_SIZE
+            && self.zstd_comp_size != 0
+            && self.zstd_comp_size <= self.comp_size
+            && self.comp_size <= self.decomp_size
+            && self.decomp_size as u64 <= self.zstd_comp_size as u64 * MAX_NODE_COMPRESSION_RATIO
+            && self.data_start as u64 + self.zstd_comp_size as u64 <= available
+    }
+}
+
+// Some fields are only read to keep the layout, their meaning isn't known yet.
+#[derive(Debug, Clone, Pread)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) struct NodeHeader {
     pub file_size: u32,
     pub folder_count: u32,
@@ -30,15 +80,20 @@ pub(crate) struct NodeHeader {
     pub sub_files1_count: u32,
     pub file_lookup_count: u32,
     pub hash_folder_count: u32,
+    #[allow(dead_code)]
     pub file_information_count: u32,
 
     pub file_count2: u32,
     pub sub_files2_count: u32,
+    #[allow(dead_code)]
     pub unk1: u32,
+    #[allow(dead_code)]
     pub unk2: u32,
 
     pub another_hash_table_size: u8,
+    #[allow(dead_code)]
     pub unk3: u8,
+    #[allow(dead_code)]
     pub unk4: u16,
 
     pub movie_count: u32,
@@ -48,8 +103,22 @@ pub(crate) struct NodeHeader {
 }
 pub(crate) const NODE_HEADER_SIZE: usize = 0x44;
 
-#[derive(Debug)]
-pub(crate) struct EntryTriplet {
+// The counts come straight from the archive, so they are widened before adding them to ensure a corrupt header can't overflow.
+impl NodeHeader {
+    /// Number of `BigFileEntry`s in `big_files`
+    pub fn big_file_count(&self) -> u64 {
+        self.file_count1 as u64 + self.file_count2 as u64
+    }
+
+    /// Number of `FileEntry`s in `sub_files1` and `sub_files2`
+    pub fn sub_file_count(&self) -> u64 {
+        self.sub_files1_count as u64 + self.sub_files2_count as u64
+    }
+}
+
+/// A 40 bit hash paired with 24 bits of data and another 32 bits of data.
+#[derive(Debug, Clone)]
+pub struct EntryTriplet {
     pub hash: u64, // 0x28 bits
     pub meta: u32, // 0x18 bits
     pub meta2: u32,
@@ -63,8 +132,9 @@ pub(crate) fn read_triplet(data: &[u8]) -> EntryTriplet {
     EntryTriplet { hash, meta, meta2 }
 }
 
-#[derive(Debug)]
-pub(crate) struct EntryPair {
+/// A 40 bit hash paired with 24 bits of data, usually an index into another table.
+#[derive(Debug, Clone)]
+pub struct EntryPair {
     pub hash: u64, // 0x28 bits
     pub meta: u32, // 0x18 bits
 }
@@ -76,8 +146,17 @@ pub(crate) fn read_pair(data: &[u8]) -> EntryPair {
     EntryPair { hash, meta }
 }
 
-#[derive(Debug)]
-pub(crate) struct BigHashEntry {
+pub(crate) fn write_pair(pair: &EntryPair, out: &mut Vec<u8>) {
+    let mut data = [0; 8];
+    LittleEndian::write_u64(&mut data, pair.hash);
+    out.extend_from_slice(&data[..5]);
+    LittleEndian::write_u32(&mut data, pair.meta);
+    out.extend_from_slice(&data[..3]);
+}
+
+/// Describes a folder: the hashes of its path components and the range of its files.
+#[derive(Debug, Clone)]
+pub struct BigHashEntry {
     pub path: EntryPair,
     pub folder: EntryPair,
     pub parent: EntryPair,
@@ -112,8 +191,9 @@ pub(crate) fn read_big_hash_entry(data: &[u8]) -> BigHashEntry {
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct TreeEntry {
+/// Describes a single path in the archive: the hashes of its components and how to locate its data.
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
     pub path: EntryPair,
     pub ext: EntryPair,
     pub folder: EntryPair,
@@ -135,24 +215,32 @@ pub(crate) fn read_tree_entry(data: &[u8]) -> TreeEntry {
 }
 
 const TREE_SUBOFFSET_MASK: u32 = 0b11;
+const TREE_REDIRECT: u32 = 0x200000;
+const TREE_KNOWN_FLAGS: u32 = TREE_SUBOFFSET_MASK | TREE_REDIRECT;
 impl TreeEntry {
     pub fn redirect(&self) -> bool {
-        self.flags & 0x200000 != 0
+        self.flags & TREE_REDIRECT != 0
+    }
+    /// The flag bits we don't know the meaning of
+    pub fn unknown_flags(&self) -> u32 {
+        self.flags & !TREE_KNOWN_FLAGS
     }
     pub fn suboffset_index(&self) -> bool {
         self.flags & TREE_SUBOFFSET_MASK == 0
     }
 }
 
-#[derive(Debug, Pread)]
-pub(crate) struct FilePair {
+/// The size and offset of a bulk file's data within the music file section.
+#[derive(Debug, Clone, Pread)]
+pub struct FilePair {
     pub size: u64,
     pub offset: u64,
 }
 pub(crate) const FILE_PAIR_SIZE: usize = 0x10;
 
-#[derive(Debug, Pread)]
-pub(crate) struct BigFileEntry {
+/// A group of files stored together in the file section, see `BigFileIndex`.
+#[derive(Debug, Clone, Pread)]
+pub struct BigFileEntry {
     pub offset: u64,
     pub decomp_size: u32,
     pub comp_size: u32,
@@ -162,8 +250,9 @@ pub(crate) struct BigFileEntry {
 }
 pub(crate) const BIG_FILE_ENTRY_SIZE: usize = 0x1c;
 
-#[derive(Debug, Pread)]
-pub(crate) struct FileEntry {
+/// Locates a single file's data relative to its big file, along with its sizes and compression.
+#[derive(Debug, Clone, Pread)]
+pub struct FileEntry {
     pub offset: u32,
     pub comp_size: u32,
     pub decomp_size: u32,
@@ -171,23 +260,26 @@ pub(crate) struct FileEntry {
 }
 pub(crate) const FILE_ENTRY_SIZE: usize = 0x10;
 
+const FILE_COMPRESSION_MASK: u32 = 0x07000000;
+const FILE_KNOWN_FLAGS: u32 = FILE_COMPRESSION_MASK;
 impl FileEntry {
-    pub fn suboffset_redir(&self) -> bool {
-        unimplemented!()
-    }
-    pub fn suboffset_tree_index(&self) -> usize {
-        unimplemented!()
-    }
     pub fn suboffset_decompressed(&self) -> bool {
-        self.flags & 0x07000000 == 0
+        self.flags & FILE_COMPRESSION_MASK == 0
     }
     pub fn suboffset_compressed_zstd(&self) -> bool {
-        self.flags & 0x07000000 == 0x03000000
+        self.flags & FILE_COMPRESSION_MASK == 0x03000000
+    }
+    /// The flag bits we don't know the meaning of
+    pub fn unknown_flags(&self) -> u32 {
+        self.flags & !FILE_KNOWN_FLAGS
     }
 }
 
-#[derive(Debug, Pread)]
-pub(crate) struct HashBucket {
+/// The bucket table starts with a header HashBucket whose `num_entries` is the number of buckets following it.
+/// For the remaining buckets `index` and `num_entries` describe a range of EntryPairs in `file_lookup`.
+#[derive(Debug, Clone, Pread)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HashBucket {
     pub index: u32,
     pub num_entries: u32,
 }
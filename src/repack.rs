@@ -0,0 +1,51 @@
+//! Building the node section tables needed to repack a modified data.arc.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::parse::{EntryPair, write_pair};
+
+/// The serialized `file_lookup_buckets` and `file_lookup` tables of the node section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashBucketTables {
+    pub file_lookup_buckets: Vec<u8>,
+    pub file_lookup: Vec<u8>,
+}
+
+/// Build the tables used to lookup a tree entry from a path hash.
+/// `entries` contains a pair for each path, mapping its hash to the index of its tree entry.
+///
+/// The bucketing matches what `DataArc` expects when looking up files:
+/// *   Each hash is assigned to bucket `hash % num_buckets`
+/// *   `file_lookup` contains every pair grouped by bucket in bucket order, the pairs within each bucket are sorted by hash
+/// *   `file_lookup_buckets` starts with a header bucket whose `num_entries` is `num_buckets` (its `index` is written as 0),
+///     followed by each bucket's index of its first pair in `file_lookup` and its number of pairs.
+///
+/// When repacking, reuse the `bucket_count` of the original archive for `num_buckets`.
+///
+/// Panics if `num_buckets` is 0.
+pub fn build_hash_buckets(entries: &[EntryPair], num_buckets: u32) -> HashBucketTables {
+    assert!(num_buckets > 0, "A hash bucket table needs at least one bucket");
+
+    let mut buckets: Vec<Vec<&EntryPair>> = vec!(vec!(); num_buckets as usize);
+    for entry in entries {
+        buckets[(entry.hash % num_buckets as u64) as usize].push(entry);
+    }
+
+    let mut file_lookup_buckets = vec!();
+    let mut file_lookup = vec!();
+    file_lookup_buckets.write_u32::<LittleEndian>(0).unwrap();
+    file_lookup_buckets.write_u32::<LittleEndian>(num_buckets).unwrap();
+
+    let mut index = 0;
+    for bucket in &mut buckets {
+        bucket.sort_by_key(|entry| entry.hash);
+        file_lookup_buckets.write_u32::<LittleEndian>(index).unwrap();
+        file_lookup_buckets.write_u32::<LittleEndian>(bucket.len() as u32).unwrap();
+        for entry in bucket.iter() {
+            write_pair(entry, &mut file_lookup);
+        }
+        index += bucket.len() as u32;
+    }
+
+    HashBucketTables { file_lookup_buckets, file_lookup }
+}
@@ -0,0 +1,97 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Turns a path stored in the data.arc, e.g. `prebuilt:/nro/release/lua2cpp_mewtwo.nro`, into a relative path that can be created on disk.
+///
+/// By default the characters `<`, `>`, `:`, `"`, `|`, `?`, `*` and `\` are each replaced with `_`, as they are not allowed in Windows file names.
+/// So the above path becomes `prebuilt_/nro/release/lua2cpp_mewtwo.nro`.
+/// The mapping can be changed with `PathSanitizer::map`.
+///
+/// Regardless of the mapping, `/` separates directories and the result can't escape the directory it is joined onto:
+/// after mapping, components that are empty, `.` or `..`, that contain `/` or `\`, or that the OS would treat as a root or prefix (e.g. `C:` on Windows) are dropped.
+/// So mapping a character to a separator drops every component containing that character.
+#[derive(Debug, Clone)]
+pub struct PathSanitizer {
+    mapping: Vec<(char, char)>,
+}
+
+impl Default for PathSanitizer {
+    fn default() -> PathSanitizer {
+        PathSanitizer {
+            mapping: ['<', '>', ':', '"', '|', '?', '*', '\\'].iter().map(|c| (*c, '_')).collect(),
+        }
+    }
+}
+
+impl PathSanitizer {
+    pub fn new() -> PathSanitizer {
+        PathSanitizer::default()
+    }
+
+    /// Replace `from` with `to`, overriding any existing replacement for `from`.
+    pub fn map(mut self, from: char, to: char) -> PathSanitizer {
+        self.mapping.retain(|(c, _)| *c != from);
+        self.mapping.push((from, to));
+        self
+    }
+
+    pub fn sanitize(&self, path: &str) -> PathBuf {
+        path.split('/')
+            .map(|component| component.chars().map(|c| self.replace(c)).collect::<String>())
+            .filter(|component| is_normal_component(component))
+            .collect()
+    }
+
+    fn replace(&self, c: char) -> char {
+        self.mapping.iter()
+            .find(|(from, _)| *from == c)
+            .map(|(_, to)| *to)
+            .unwrap_or(c)
+    }
+}
+
+/// Returns true if `component` is a single normal path component, so joining it onto a directory stays within that directory.
+fn is_normal_component(component: &str) -> bool {
+    if component.contains(['/', '\\']) {
+        return false;
+    }
+    let mut components = Path::new(component).components();
+    matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_mapping() {
+        let path = PathSanitizer::default().sanitize("prebuilt:/nro/release/lua2cpp_mewtwo.nro");
+        assert_eq!(path, PathBuf::from("prebuilt_/nro/release/lua2cpp_mewtwo.nro"));
+        for component in path.components() {
+            let component = component.as_os_str().to_str().unwrap();
+            assert!(!component.contains(['<', '>', ':', '"', '|', '?', '*', '\\']), "{} is not a valid Windows file name", component);
+        }
+    }
+
+    #[test]
+    fn dot_components_are_dropped() {
+        let path = PathSanitizer::default().sanitize("/../a/./b//../c");
+        assert_eq!(path, PathBuf::from("a/b/c"));
+    }
+
+    #[test]
+    fn mapping_to_a_separator_cant_escape() {
+        let sanitizer = PathSanitizer::default().map('_', '/').map('~', '\\').map('-', '.');
+        assert_eq!(sanitizer.sanitize("_etc_passwd"), PathBuf::new());
+        assert_eq!(sanitizer.sanitize("a/~~/b"), PathBuf::from("a/b"));
+        assert_eq!(sanitizer.sanitize("a/--/b"), PathBuf::from("a/b"));
+        assert_eq!(sanitizer.sanitize("a/.._b/c"), PathBuf::from("a/c"));
+    }
+
+    #[test]
+    fn unmapped_drive_prefix_cant_escape() {
+        let sanitizer = PathSanitizer::default().map(':', ':');
+        let path = sanitizer.sanitize("C:/Windows");
+        assert!(path.is_relative());
+        assert!(path.ends_with("Windows"));
+    }
+}
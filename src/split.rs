@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::{self, Error as IOError, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Reads a data.arc that has been split into multiple parts, e.g. `data.arc.part0`, `data.arc.part1`, ..., as if it were a single file.
+/// Pass it to `DataArc::new` to parse a split data.arc without joining the parts first.
+///
+/// The parts must be in order: the first part is the start of the data.arc and each part continues exactly where the previous part ends.
+/// Parts can be any size, including empty. The size of each part is measured when the `SplitReader` is created,
+/// so the parts must not change size afterwards.
+pub struct SplitReader<R = File> {
+    parts: Vec<R>,
+    /// The offset each part starts at, followed by the total size of all the parts
+    starts: Vec<u64>,
+    position: u64,
+}
+
+impl SplitReader<File> {
+    /// Open the files at `paths` as the parts, in the passed order.
+    pub fn open(paths: &[impl AsRef<Path>]) -> io::Result<SplitReader<File>> {
+        let parts = paths.iter().map(File::open).collect::<io::Result<Vec<File>>>()?;
+        SplitReader::new(parts)
+    }
+}
+
+impl<R: Read + Seek> SplitReader<R> {
+    /// Read `parts` in the passed order as a single file.
+    pub fn new(mut parts: Vec<R>) -> io::Result<SplitReader<R>> {
+        let mut starts = vec!(0);
+        for part in &mut parts {
+            let len = part.seek(SeekFrom::End(0))?;
+            starts.push(starts[starts.len() - 1] + len);
+        }
+        Ok(SplitReader { parts, starts, position: 0 })
+    }
+
+    fn len(&self) -> u64 {
+        self.starts[self.starts.len() - 1]
+    }
+}
+
+impl<R: Read + Seek> Read for SplitReader<R> {
+    /// Reads stop at the end of each part, so a read spanning two parts returns fewer bytes than requested.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.len() {
+            return Ok(0);
+        }
+
+        // The last part starting at or before our position, which skips over any empty parts
+        let index = self.starts.partition_point(|start| *start <= self.position) - 1;
+        let remaining = self.starts[index + 1] - self.position;
+        let len = (buf.len() as u64).min(remaining) as usize;
+
+        let part = &mut self.parts[index];
+        part.seek(SeekFrom::Start(self.position - self.starts[index]))?;
+        let read = part.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(IOError::new(ErrorKind::UnexpectedEof, format!("Part {} ended before the size it had when the SplitReader was created", index)));
+        }
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for SplitReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| IOError::new(ErrorKind::InvalidInput, "Tried to seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn read_across_parts() {
+        let data: Vec<u8> = (0..100).collect();
+        let parts = vec!(
+            Cursor::new(data[..10].to_vec()),
+            Cursor::new(vec!()),
+            Cursor::new(data[10..11].to_vec()),
+            Cursor::new(data[11..].to_vec()),
+        );
+        let mut reader = SplitReader::new(parts).unwrap();
+
+        let mut all = vec!();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+
+        let mut buffer = [0; 20];
+        assert_eq!(reader.seek(SeekFrom::Start(5)).unwrap(), 5);
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer[..], &data[5..25]);
+
+        assert_eq!(reader.seek(SeekFrom::Current(-15)).unwrap(), 10);
+        reader.read_exact(&mut buffer[..2]).unwrap();
+        assert_eq!(&buffer[..2], &data[10..12]);
+
+        assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), 97);
+        assert!(reader.read_exact(&mut buffer[..4]).is_err());
+        assert!(reader.seek(SeekFrom::Current(-1000)).is_err());
+
+        // Reading past the end reads nothing
+        assert_eq!(reader.seek(SeekFrom::Start(1000)).unwrap(), 1000);
+        assert_eq!(reader.read(&mut buffer).unwrap(), 0);
+    }
+}
@@ -0,0 +1,293 @@
+//! Builds small synthetic data.arc files for the tests.
+//!
+//! Every file is stored in a single big file in a single folder, and is looked up through the hash buckets
+//! built by `repack::build_hash_buckets`, so the archives exercise the same resolution chain as a real data.arc.
+
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::hash::hash40;
+use crate::parse::{write_pair, EntryPair, NODE_HEADER_SIZE};
+use crate::repack::build_hash_buckets;
+use crate::DataArc;
+
+/// `FileEntry` flags of a zstd compressed file
+pub(crate) const ZSTD: u32 = 0x03000000;
+/// `TreeEntry` flag marking a redirect
+pub(crate) const REDIRECT: u32 = 0x200000;
+
+/// A file to store in a `TestArchive`.
+#[derive(Clone)]
+pub(crate) struct TestFile {
+    pub path: String,
+    /// The decompressed contents
+    pub data: Vec<u8>,
+    /// `FileEntry` flags, `ZSTD` compresses `data`. Any other value stores `data` as is.
+    pub file_flags: u32,
+    pub tree_flags: u32,
+    /// Stored in place of `data` (compressed or not), e.g. to corrupt the file
+    pub stored: Option<Vec<u8>>,
+    /// Stored in place of the hash of the path's file name
+    pub file_name_hash: Option<u64>,
+    /// Stored in place of the hash of the path's extension
+    pub ext_hash: Option<u64>,
+    /// Stored as the `meta` of the extension, the sub file index used when either of the low two tree flags is set
+    pub ext_meta: Option<u32>,
+    /// Stored in place of the index of the file's own sub file, e.g. the index of a redirect's target tree
+    pub suboffset_index: Option<u32>,
+    /// Index of an earlier file whose data this file shares instead of storing its own
+    pub share: Option<usize>,
+}
+
+impl TestFile {
+    pub fn stored(path: &str, data: &[u8]) -> TestFile {
+        TestFile {
+            path: path.to_string(),
+            data: data.to_vec(),
+            file_flags: 0,
+            tree_flags: 0,
+            stored: None,
+            file_name_hash: None,
+            ext_hash: None,
+            ext_meta: None,
+            suboffset_index: None,
+            share: None,
+        }
+    }
+
+    pub fn zstd(path: &str, data: &[u8]) -> TestFile {
+        TestFile { file_flags: ZSTD, ..TestFile::stored(path, data) }
+    }
+
+    fn stored_data(&self) -> Vec<u8> {
+        match &self.stored {
+            Some(stored) => stored.clone(),
+            None if self.file_flags & 0x07000000 == ZSTD => zstd::block::compress(&self.data, 3).unwrap(),
+            None => self.data.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct TestArchive {
+    pub files: Vec<TestFile>,
+    /// Name and contents of each bulk file
+    pub bulk_files: Vec<(String, Vec<u8>)>,
+    /// Defaults to 1
+    pub num_buckets: Option<u32>,
+    pub compress_node: bool,
+    /// Number of zstd frames the compressed node section is split into, defaults to 1
+    pub node_frames: Option<usize>,
+    /// Stored in place of the `file_lookup` pairs built from the tree entries
+    pub file_lookup: Option<Vec<EntryPair>>,
+    /// Bytes inserted into the node section before the named table, e.g. `("trees", vec!(0; 4))`
+    pub padding: Vec<(&'static str, Vec<u8>)>,
+}
+
+impl TestArchive {
+    pub fn new(files: Vec<TestFile>) -> TestArchive {
+        TestArchive { files, ..TestArchive::default() }
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut out = vec!();
+        out.write_u64::<LittleEndian>(0xabcdef9876543210).unwrap();
+        out.extend_from_slice(&[0; 0x28]);
+
+        // file section
+        let file_section_offset = out.len() as u64;
+        let mut sub_files = vec!();
+        for file in &self.files {
+            let sub_file = match file.share {
+                Some(index) => sub_files[index],
+                None => {
+                    let stored = file.stored_data();
+                    let offset = (out.len() as u64 - file_section_offset) / 4;
+                    out.extend_from_slice(&stored);
+                    align(&mut out);
+                    (offset as u32, stored.len() as u32)
+                }
+            };
+            sub_files.push(sub_file);
+        }
+        let file_section_size = out.len() as u64 - file_section_offset;
+
+        // music file section
+        let music_file_section_offset = out.len() as u64;
+        let mut file_pairs = vec!();
+        for (_, data) in &self.bulk_files {
+            file_pairs.push((data.len() as u64, out.len() as u64 - music_file_section_offset));
+            out.extend_from_slice(data);
+            align(&mut out);
+        }
+
+        let node_section_offset = out.len() as u64;
+        let node = self.node(&sub_files, &file_pairs, file_section_size);
+        if self.compress_node {
+            let frame_len = node.len().div_ceil(self.node_frames.unwrap_or(1));
+            let compressed: Vec<u8> = node.chunks(frame_len)
+                .flat_map(|frame| zstd::block::compress(frame, 3).unwrap())
+                .collect();
+            assert!(compressed.len() <= node.len(), "The node section needs to be compressible for it to be detected as compressed");
+            out.write_u32::<LittleEndian>(0x10).unwrap();
+            out.write_u32::<LittleEndian>(node.len() as u32).unwrap();
+            out.write_u32::<LittleEndian>(compressed.len() as u32).unwrap();
+            out.write_u32::<LittleEndian>(compressed.len() as u32).unwrap();
+            out.extend_from_slice(&compressed);
+        } else {
+            out.extend_from_slice(&node);
+        }
+        let end = out.len() as u64;
+
+        let mut header = vec!();
+        for offset in &[music_file_section_offset, file_section_offset, node_section_offset, node_section_offset, end] {
+            header.write_u64::<LittleEndian>(*offset).unwrap();
+        }
+        out[8..0x30].copy_from_slice(&header);
+        out
+    }
+
+    /// The NodeHeader followed by the node tables
+    fn node(&self, sub_files: &[(u32, u32)], file_pairs: &[(u64, u64)], file_section_size: u64) -> Vec<u8> {
+        let mut tables = vec!();
+
+        // bulkfile_hash_lookup, bulkfiles_by_name and bulkfile_lookup_to_fileidx
+        self.pad("bulkfile_hash_lookup", &mut tables);
+        for (i, (name, _)) in self.bulk_files.iter().enumerate() {
+            write_pair(&EntryPair { hash: hash40(name), meta: i as u32 }, &mut tables);
+        }
+        self.pad("bulkfiles_by_name", &mut tables);
+        for (i, (name, _)) in self.bulk_files.iter().enumerate() {
+            write_pair(&EntryPair { hash: hash40(name), meta: i as u32 }, &mut tables);
+            tables.write_u32::<LittleEndian>(0).unwrap();
+        }
+        self.pad("bulkfile_lookup_to_fileidx", &mut tables);
+        for i in 0..self.bulk_files.len() {
+            tables.write_u32::<LittleEndian>(i as u32).unwrap();
+        }
+
+        // file_pairs
+        self.pad("file_pairs", &mut tables);
+        for (size, offset) in file_pairs {
+            tables.write_u64::<LittleEndian>(*size).unwrap();
+            tables.write_u64::<LittleEndian>(*offset).unwrap();
+        }
+
+        // big_hashes: a single folder in big file 0
+        self.pad("big_hashes", &mut tables);
+        write_pair(&EntryPair { hash: hash40(""), meta: 0 }, &mut tables);
+        tables.extend_from_slice(&[0; 0x2c]);
+
+        // big_files: a single big file at the start of the file section
+        self.pad("big_files", &mut tables);
+        tables.write_u64::<LittleEndian>(0).unwrap();
+        tables.write_u32::<LittleEndian>(file_section_size as u32).unwrap();
+        tables.write_u32::<LittleEndian>(file_section_size as u32).unwrap();
+        tables.write_u32::<LittleEndian>(0).unwrap();
+        tables.write_u32::<LittleEndian>(self.files.len() as u32).unwrap();
+        tables.write_u32::<LittleEndian>(0).unwrap();
+
+        // trees
+        self.pad("trees", &mut tables);
+        for (i, file) in self.files.iter().enumerate() {
+            let (folder, name) = file.path.rsplit_once('/').unwrap_or(("", &file.path));
+            let ext = name.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("");
+            write_pair(&EntryPair { hash: hash40(&file.path), meta: 0 }, &mut tables);
+            write_pair(&EntryPair { hash: file.ext_hash.unwrap_or_else(|| hash40(ext)), meta: file.ext_meta.unwrap_or(0) }, &mut tables);
+            write_pair(&EntryPair { hash: hash40(folder), meta: 0 }, &mut tables);
+            write_pair(&EntryPair { hash: file.file_name_hash.unwrap_or_else(|| hash40(name)), meta: 0 }, &mut tables);
+            tables.write_u32::<LittleEndian>(file.suboffset_index.unwrap_or(i as u32)).unwrap();
+            tables.write_u32::<LittleEndian>(file.tree_flags).unwrap();
+        }
+
+        // sub_files1
+        self.pad("sub_files1", &mut tables);
+        for (file, (offset, comp_size)) in self.files.iter().zip(sub_files) {
+            tables.write_u32::<LittleEndian>(*offset).unwrap();
+            tables.write_u32::<LittleEndian>(*comp_size).unwrap();
+            tables.write_u32::<LittleEndian>(file.data.len() as u32).unwrap();
+            tables.write_u32::<LittleEndian>(file.file_flags).unwrap();
+        }
+
+        // folder_to_big_hash
+        self.pad("folder_to_big_hash", &mut tables);
+        tables.extend_from_slice(&[0; 8]);
+
+        // file_lookup_buckets and file_lookup
+        let file_lookup = self.file_lookup.clone().unwrap_or_else(|| {
+            self.files.iter().enumerate()
+                .map(|(i, file)| EntryPair { hash: hash40(&file.path), meta: i as u32 })
+                .collect()
+        });
+        let buckets = build_hash_buckets(&file_lookup, self.num_buckets.unwrap_or(1));
+        self.pad("file_lookup_buckets", &mut tables);
+        tables.extend_from_slice(&buckets.file_lookup_buckets);
+        self.pad("file_lookup", &mut tables);
+        tables.extend_from_slice(&buckets.file_lookup);
+
+        // numbers, so that reading the first entry of every table stays within the node section
+        tables.extend_from_slice(&[0; 8]);
+
+        let bulk_count = self.bulk_files.len() as u32;
+        let mut node = vec!();
+        for value in &[
+            (NODE_HEADER_SIZE + tables.len()) as u32, // file_size
+            1, // folder_count
+            1, // file_count1
+            self.files.len() as u32, // tree_count
+            self.files.len() as u32, // sub_files1_count
+            file_lookup.len() as u32, // file_lookup_count
+            0, // hash_folder_count
+            0, // file_information_count
+            0, // file_count2
+            0, // sub_files2_count
+            0, // unk1
+            0, // unk2
+            0, // another_hash_table_size, unk3 and unk4
+            0, // movie_count
+            bulk_count, // part1_count
+            bulk_count, // part2_count
+            bulk_count, // music_file_count
+        ] {
+            node.write_u32::<LittleEndian>(*value).unwrap();
+        }
+        node.extend_from_slice(&tables);
+        node
+    }
+
+    fn pad(&self, table: &str, tables: &mut Vec<u8>) {
+        for (name, padding) in &self.padding {
+            if *name == table {
+                tables.extend_from_slice(padding);
+            }
+        }
+    }
+
+    pub fn open(&self) -> DataArc<Cursor<Vec<u8>>> {
+        DataArc::new(Cursor::new(self.build())).unwrap()
+    }
+
+    pub fn write_to(&self, path: &Path) {
+        std::fs::write(path, self.build()).unwrap();
+    }
+}
+
+fn align(out: &mut Vec<u8>) {
+    while !out.len().is_multiple_of(4) {
+        out.push(0);
+    }
+}
+
+/// A new empty directory under the system temp directory, unique to this test run.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "ultimate_data_arc_{}_{}_{}", std::process::id(), COUNT.fetch_add(1, Ordering::SeqCst), name
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,1312 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::convert::TryInto;
+use std::io::{self, Cursor};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::hash::hash40;
+use crate::parse::{read_pair, EntryPair, TreeIndex, ENTRY_PAIR_SIZE};
+use crate::repack::build_hash_buckets;
+use crate::test_archive::{temp_dir, TestArchive, TestFile, REDIRECT, ZSTD};
+use crate::{extract, Compression, BigFileIndex, DataArc, ExtractError, ExtractOptions, ExtractReport, FolderContents, PathSanitizer, SharedFiles, GetFileError, OverlayArc, ParseError, PathHasher, PathNode, PathNodeKind, OpenStage, ProblemKind, LOCATION_CACHE_CAPACITY, SplitReader, UnknownFlagPolicy};
+
+#[test]
+fn unknown_flag_policy() {
+    let archive = TestArchive::new(vec!(
+        TestFile { file_flags: 0x10, ..TestFile::stored("a/unknown_file_flag.bin", b"file flag") },
+        TestFile { tree_flags: 0x100, ..TestFile::stored("a/unknown_tree_flag.bin", b"tree flag") },
+        TestFile { file_flags: ZSTD | 0x10, ..TestFile::zstd("a/unknown_zstd_flag.bin", b"zstd flag") },
+    ));
+    let mut arc = archive.open();
+
+    for (path, data) in &[("a/unknown_file_flag.bin", b"file flag"), ("a/unknown_tree_flag.bin", b"tree flag"), ("a/unknown_zstd_flag.bin", b"zstd flag")] {
+        arc.set_unknown_flag_policy(UnknownFlagPolicy::Error);
+        assert!(matches!(arc.get_file(path), Err(GetFileError::Unsupported(_))));
+
+        arc.set_unknown_flag_policy(UnknownFlagPolicy::Ignore);
+        assert_eq!(arc.get_file(path).unwrap(), &data[..]);
+
+        arc.set_unknown_flag_policy(UnknownFlagPolicy::Warn);
+        assert_eq!(arc.get_file(path).unwrap(), &data[..]);
+    }
+}
+
+/// Hashes paths as if they were in the `prefixed` folder
+struct PrefixHasher;
+
+impl PathHasher for PrefixHasher {
+    fn hash(&self, path: &str) -> u64 {
+        hash40(&format!("prefixed/{}", path))
+    }
+}
+
+#[test]
+fn set_hasher() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("a.bin", b"unprefixed"),
+        TestFile::stored("prefixed/a.bin", b"prefixed"),
+    )).open();
+
+    assert_eq!(arc.get_file("a.bin").unwrap(), b"unprefixed");
+    arc.set_hasher(PrefixHasher);
+    assert_eq!(arc.get_file("a.bin").unwrap(), b"prefixed");
+    assert!(matches!(arc.get_file("prefixed/a.bin"), Err(GetFileError::FileNotFound)));
+    // Hashes are used as is
+    assert_eq!(arc.get_file_by_hash(hash40("a.bin")).unwrap(), b"unprefixed");
+}
+
+#[test]
+fn data_arc_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() { }
+    assert_send_sync::<DataArc<File>>();
+    assert_send_sync::<OverlayArc<File>>();
+}
+
+#[test]
+fn from_path() {
+    let path = temp_dir("from_path").join("data.arc");
+    TestArchive::new(vec!(TestFile::zstd("a.bin", b"contents"))).write_to(&path);
+    let mut arc = DataArc::from_path(&path).unwrap();
+    assert_eq!(arc.get_file("a.bin").unwrap(), b"contents");
+}
+
+#[test]
+fn get_file_range() {
+    let data: Vec<u8> = (0..0x3000).map(|i| (i * 7 % 251) as u8).collect();
+    let mut arc = TestArchive::new(vec!(
+        TestFile::zstd("compressed.bin", &data),
+        TestFile::stored("stored.bin", &data),
+    )).open();
+
+    for path in &["compressed.bin", "stored.bin"] {
+        let hash = hash40(path);
+        let file = arc.get_file(path).unwrap();
+        for range in &[0..0, 0..0x10, 0x1234..0x2345, 0x2ff0..0x3000, 0..0x3000] {
+            assert_eq!(arc.get_file_range(hash, range.clone()).unwrap(), &file[range.start as usize..range.end as usize]);
+        }
+        assert!(matches!(arc.get_file_range(hash, 0x2ff0..0x3001), Err(GetFileError::InvalidRange { .. })));
+    }
+}
+
+#[test]
+fn problem_files() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("ok.bin", b"ok"),
+        TestFile { file_flags: 0x01000000, ..TestFile::stored("unknown_compression.bin", b"unknown") },
+        TestFile { tree_flags: 0b01, ..TestFile::stored("sub_file_redirect.bin", b"sub file redirect") },
+        TestFile { stored: Some(vec!()), suboffset_index: Some(4), ..TestFile::stored("out_of_range_index.bin", b"") },
+    )).open();
+    assert_eq!(arc.problem_files(), vec!(
+        (hash40("unknown_compression.bin"), ProblemKind::UnknownCompression),
+        (hash40("sub_file_redirect.bin"), ProblemKind::SubFileRedirect),
+        (hash40("out_of_range_index.bin"), ProblemKind::OutOfRange),
+    ));
+
+    // Past the end of the data.arc
+    let file_len = arc.file_len;
+    arc.set_file_section_offset(file_len);
+    assert!(arc.problem_files().contains(&(hash40("ok.bin"), ProblemKind::OutOfRange)));
+}
+
+#[test]
+fn sub_file_index_branches() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile { suboffset_index: Some(0), ..TestFile::stored("a/index_of_first.bin", b"second") },
+        TestFile { suboffset_index: Some(5), ..TestFile::stored("a/index_out_of_range.bin", b"") },
+        TestFile { tree_flags: 0b01, ext_meta: Some(0), ..TestFile::stored("a/ext_meta.bin", b"") },
+        TestFile { tree_flags: 0b10, ext_meta: Some(5), ..TestFile::stored("a/ext_meta_out_of_range.bin", b"") },
+    )).open();
+
+    // Both flag bits clear: `suboffset_index` is the sub file, whichever file it belongs to
+    assert_eq!(arc.get_file("a/first.bin").unwrap(), b"first");
+    assert_eq!(arc.get_file("a/index_of_first.bin").unwrap(), b"first");
+    assert!(matches!(arc.get_file("a/index_out_of_range.bin"), Err(GetFileError::Corrupt(_))));
+
+    // Either flag bit set: `ext.meta` is bounds checked but following it isn't implemented
+    assert!(matches!(arc.get_file("a/ext_meta.bin"), Err(GetFileError::Unsupported(_))));
+    assert!(matches!(arc.get_file("a/ext_meta_out_of_range.bin"), Err(GetFileError::Corrupt(_))));
+
+    assert_eq!(arc.problem_files(), vec!(
+        (hash40("a/index_out_of_range.bin"), ProblemKind::OutOfRange),
+        (hash40("a/ext_meta.bin"), ProblemKind::SubFileRedirect),
+        (hash40("a/ext_meta_out_of_range.bin"), ProblemKind::OutOfRange),
+    ));
+}
+
+#[test]
+fn overflowing_offsets() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("first.bin", b"first"),
+        TestFile::stored("second.bin", b"second"),
+    )).open();
+    arc.set_file_section_offset(u64::MAX - 2);
+
+    for path in &["first.bin", "second.bin"] {
+        let hash = hash40(path);
+        assert!(matches!(arc.get_file(path), Err(GetFileError::Corrupt(_))));
+        assert!(matches!(arc.get_file_range(hash, 0..1), Err(GetFileError::Corrupt(_))));
+        assert!(matches!(arc.required_big_files(&[hash]), Err(GetFileError::Corrupt(_))));
+        assert!(arc.problem_files().contains(&(hash, ProblemKind::OutOfRange)));
+    }
+    assert!(matches!(arc.file_metadata("second.bin"), Err(GetFileError::Corrupt(_))));
+    assert_eq!(arc.check_alignment(4), vec!(hash40("first.bin")));
+    assert_eq!(arc.content_dedup().failed.len(), 2);
+}
+
+#[test]
+fn compressed_node_section() {
+    let files = vec!(
+        TestFile::zstd("a/compressed.bin", &[1; 0x100]),
+        TestFile::stored("a/stored.bin", b"stored"),
+    );
+    let uncompressed = TestArchive::new(files.clone());
+    let compressed = TestArchive { compress_node: true, ..TestArchive::new(files) };
+
+    let mut uncompressed_arc = uncompressed.open();
+    let mut compressed_arc = compressed.open();
+    assert!(uncompressed_arc.compressed_node.is_none());
+    assert!(compressed_arc.compressed_node.is_some());
+    // The node tables are the same either way
+    assert_eq!(compressed_arc.buffer, uncompressed_arc.buffer);
+    for path in &["a/compressed.bin", "a/stored.bin"] {
+        assert_eq!(compressed_arc.get_file(path).unwrap(), uncompressed_arc.get_file(path).unwrap());
+    }
+
+    // Reopening from a snapshot rereads the compressed node section
+    let snapshot = compressed_arc.snapshot();
+    let mut reopened = DataArc::from_snapshot(Cursor::new(compressed.build()), snapshot).unwrap();
+    assert_eq!(reopened.buffer, compressed_arc.buffer);
+    assert_eq!(reopened.get_file("a/compressed.bin").unwrap(), vec!(1; 0x100));
+
+    // Every frame of a node section split into multiple frames is decompressed
+    let two_frames = TestArchive { compress_node: true, node_frames: Some(2), ..TestArchive::new(uncompressed.files.clone()) };
+    let two_frames_data = two_frames.build();
+    let single_frame_data = compressed.build();
+    let frames = |data: &[u8]| data.windows(4).filter(|window| *window == [0x28, 0xb5, 0x2f, 0xfd]).count();
+    assert_eq!(frames(&two_frames_data), frames(&single_frame_data) + 1);
+    let mut two_frames_arc = DataArc::new(Cursor::new(two_frames_data)).unwrap();
+    assert_eq!(two_frames_arc.buffer, uncompressed_arc.buffer);
+    assert_eq!(two_frames_arc.get_file("a/compressed.bin").unwrap(), vec!(1; 0x100));
+}
+
+#[test]
+fn bucket_search_matches_linear_scan() {
+    let files = (0..40).map(|i| TestFile::stored(&format!("folder/file_{}.bin", i), format!("{}", i).as_bytes())).collect();
+    let mut arc = TestArchive { num_buckets: Some(7), ..TestArchive::new(files) }.open();
+
+    let sizes: Vec<u32> = (0..arc.bucket_count()).map(|i| arc.bucket(i).unwrap().num_entries).collect();
+    assert!(sizes.iter().any(|size| *size != sizes[0]), "The buckets should have different sizes: {:?}", sizes);
+
+    let linear_scan = |hash: u64| {
+        let bucket = arc.bucket(arc.bucket_index(hash).unwrap()).unwrap();
+        (bucket.index..bucket.index + bucket.num_entries)
+            .map(|i| read_pair(&arc.buffer[arc.file_lookup + ENTRY_PAIR_SIZE * i as usize..]))
+            .find(|entry| entry.hash == hash)
+            .map(|entry| entry.meta)
+    };
+
+    let missing = (40..80).map(|i| hash40(&format!("folder/file_{}.bin", i)));
+    for hash in arc.hashes().chain(missing) {
+        let bucket = arc.bucket(arc.bucket_index(hash).unwrap()).unwrap();
+        let found = arc.bucket_search(hash, &bucket).ok().map(|entry| entry.meta);
+        assert_eq!(found, linear_scan(hash), "0x{:x}", hash);
+    }
+    for i in 0..40 {
+        assert_eq!(arc.get_file(&format!("folder/file_{}.bin", i)).unwrap(), format!("{}", i).as_bytes());
+    }
+}
+
+#[test]
+fn bucket_collisions() {
+    let files = vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile::stored("a/second.bin", b"second"),
+    );
+    // With a single bucket every hash collides on the bucket
+    let mut arc = TestArchive { num_buckets: Some(1), ..TestArchive::new(files.clone()) }.open();
+    assert_eq!(arc.max_bucket_len(), 2);
+    assert_eq!(arc.get_file("a/first.bin").unwrap(), b"first");
+    assert_eq!(arc.get_file("a/second.bin").unwrap(), b"second");
+    assert!(matches!(arc.get_file("a/third.bin"), Err(GetFileError::FileNotFound)));
+
+    // A lookup entry pointing at another path's tree entry is rejected rather than returning the other file
+    let file_lookup = vec!(
+        EntryPair { hash: hash40("a/first.bin"), meta: 1 },
+        EntryPair { hash: hash40("a/second.bin"), meta: 1 },
+    );
+    let mut arc = TestArchive { num_buckets: Some(1), file_lookup: Some(file_lookup), ..TestArchive::new(files) }.open();
+    assert!(matches!(arc.get_file("a/first.bin"), Err(GetFileError::FileNotFound)));
+    assert_eq!(arc.get_file("a/second.bin").unwrap(), b"second");
+}
+
+#[test]
+fn location_cache() {
+    let files = vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile::stored("a/second.bin", b"second"),
+    );
+    let mut arc = TestArchive::new(files).open();
+    assert_eq!(arc.get_file("a/first.bin").unwrap(), b"first");
+
+    // Empty every bucket, so only cached lookups can still find their file
+    let buckets = arc.file_lookup_buckets + 8;
+    for byte in &mut arc.buffer[buckets..arc.file_lookup] {
+        *byte = 0;
+    }
+    assert_eq!(arc.get_file("a/first.bin").unwrap(), b"first");
+    assert!(matches!(arc.get_file("a/second.bin"), Err(GetFileError::FileNotFound)));
+
+    arc.clear_location_cache();
+    assert!(matches!(arc.get_file("a/first.bin"), Err(GetFileError::FileNotFound)));
+}
+
+#[test]
+fn location_cache_is_bounded() {
+    let files = (0..LOCATION_CACHE_CAPACITY + 10).map(|i| TestFile::stored(&format!("{}.bin", i), b"")).collect();
+    let mut arc = TestArchive::new(files).open();
+    for i in 0..LOCATION_CACHE_CAPACITY + 10 {
+        arc.file_metadata(&format!("{}.bin", i)).unwrap();
+        assert!(arc.location_cache.len() <= LOCATION_CACHE_CAPACITY);
+    }
+    assert!(!arc.location_cache.is_empty());
+}
+
+#[test]
+fn sanity_probe() {
+    let mut arc = TestArchive::new(vec!(TestFile::stored("a/first.bin", b"first"))).open();
+    let probe = arc.sanity_probe();
+    assert!(probe.passed(), "{:?}", probe);
+
+    // Point the first tree's path at a big_hashes entry past the only folder
+    let trees = arc.trees;
+    arc.buffer[trees + 5..trees + 8].copy_from_slice(&[5, 0, 0]);
+    let probe = arc.sanity_probe();
+    assert!(!probe.passed());
+    let failures: Vec<&str> = probe.failures().map(|check| check.section).collect();
+    assert_eq!(failures, vec!("trees"));
+}
+
+#[test]
+fn content_dedup() {
+    // Identical contents stored twice
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"duplicate"),
+        TestFile::stored("b/second.bin", b"duplicate"),
+        TestFile::stored("c/unique.bin", b"unique"),
+    )).open();
+    let report = arc.content_dedup();
+    assert_eq!(report.groups.len(), 1);
+    let group = &report.groups[0];
+    let mut hashes = group.hashes.clone();
+    hashes.sort_unstable();
+    let mut expected = vec!(hash40("a/first.bin"), hash40("b/second.bin"));
+    expected.sort_unstable();
+    assert_eq!(hashes, expected);
+    assert_eq!(group.decomp_size, 9);
+    assert_eq!(group.copy_comp_sizes, vec!(9, 9));
+    assert_eq!(report.wasted_bytes(), 9);
+    assert!(report.failed.is_empty());
+
+    // Identical contents already stored once
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"duplicate"),
+        TestFile { share: Some(0), ..TestFile::stored("b/second.bin", b"duplicate") },
+    )).open();
+    let report = arc.content_dedup();
+    assert_eq!(report.groups.len(), 1);
+    assert_eq!(report.groups[0].copy_comp_sizes, vec!(9));
+    assert_eq!(report.wasted_bytes(), 0);
+}
+
+#[test]
+fn overlay() {
+    let patch = TestArchive::new(vec!(
+        TestFile::stored("a/shared.bin", b"patch"),
+        TestFile::stored("a/patch_only.bin", b"patch only"),
+    )).open();
+    let base = TestArchive::new(vec!(
+        TestFile::stored("a/shared.bin", b"base"),
+        TestFile::stored("a/base_only.bin", b"base only"),
+    )).open();
+    let mut overlay = OverlayArc::new(vec!(patch, base));
+
+    assert_eq!(overlay.get_file("a/shared.bin").unwrap(), b"patch");
+    assert_eq!(overlay.get_file("a/patch_only.bin").unwrap(), b"patch only");
+    assert_eq!(overlay.get_file("a/base_only.bin").unwrap(), b"base only");
+    assert_eq!(overlay.get_file_by_hash(hash40("a/base_only.bin")).unwrap(), b"base only");
+    assert!(overlay.contains(hash40("a/base_only.bin")));
+    assert!(!overlay.contains(hash40("a/missing.bin")));
+    assert!(matches!(overlay.get_file("a/missing.bin"), Err(GetFileError::FileNotFound)));
+}
+
+#[test]
+fn write_atomic_failure() {
+    let dir = temp_dir("write_atomic_failure");
+    let path = dir.join("file.bin");
+    let result = crate::write_atomic(&path, false, |file| {
+        io::Write::write_all(file, b"partial")?;
+        Err(io::Error::other("interrupted"))
+    });
+    assert_eq!(result.unwrap_err().to_string(), "interrupted");
+    assert!(!path.exists());
+    assert!(!dir.join("file.bin.part").exists());
+
+    crate::write_atomic(&path, false, |file| io::Write::write_all(file, b"complete")).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"complete");
+    assert!(!dir.join("file.bin.part").exists());
+}
+
+#[test]
+fn strict_paths() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("a/plain.bin", b"plain"),
+        // As if the tree entry was for a different path whose full hash collides with this one
+        TestFile { file_name_hash: Some(hash40("other.bin")), ..TestFile::stored("a/wrong_name.bin", b"wrong name") },
+        TestFile { ext_hash: Some(hash40("txt")), ..TestFile::stored("a/wrong_ext.bin", b"wrong ext") },
+        TestFile { ext_hash: Some(hash40(".bin")), ..TestFile::stored("a/dotted_ext.bin", b"dotted ext") },
+    )).open();
+
+    for path in &["a/plain.bin", "a/wrong_name.bin", "a/wrong_ext.bin", "a/dotted_ext.bin"] {
+        assert!(arc.get_file(path).is_ok());
+    }
+
+    arc.set_strict_paths(true);
+    assert_eq!(arc.get_file("a/plain.bin").unwrap(), b"plain");
+    assert_eq!(arc.get_file("a/dotted_ext.bin").unwrap(), b"dotted ext");
+    assert!(matches!(arc.get_file("a/wrong_name.bin"), Err(GetFileError::FileNotFound)));
+    assert!(matches!(arc.get_file("a/wrong_ext.bin"), Err(GetFileError::FileNotFound)));
+}
+
+#[test]
+fn file_metadata_strict_paths() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::zstd("a/plain.bin", &[0; 0x100]),
+        TestFile { file_name_hash: Some(hash40("other.bin")), ..TestFile::stored("a/wrong_name.bin", b"wrong name") },
+    )).open();
+
+    let metadata = arc.file_metadata("a/plain.bin").unwrap();
+    assert_eq!(metadata.compression, Compression::Zstd);
+    assert_eq!(metadata.decomp_size, 0x100);
+    assert!(arc.file_metadata("a/wrong_name.bin").is_ok());
+
+    arc.set_strict_paths(true);
+    assert!(arc.file_metadata("a/plain.bin").is_ok());
+    assert!(matches!(arc.file_metadata("a/wrong_name.bin"), Err(GetFileError::FileNotFound)));
+    assert!(arc.file_metadata_by_hash(hash40("a/wrong_name.bin")).is_ok());
+}
+
+#[test]
+fn zstd_and_stored_files() {
+    let compressed: Vec<u8> = b"compressed ".iter().cycle().take(0x1000).cloned().collect();
+    let mut arc = TestArchive::new(vec!(
+        TestFile::zstd("a/compressed.bin", &compressed),
+        TestFile::stored("a/stored.bin", b"stored data"),
+    )).open();
+
+    assert_eq!(arc.file_metadata("a/compressed.bin").unwrap().compression, Compression::Zstd);
+    assert_eq!(arc.file_metadata("a/stored.bin").unwrap().compression, Compression::None);
+
+    assert_eq!(arc.get_file("a/compressed.bin").unwrap(), compressed);
+    assert_eq!(arc.get_file("a/stored.bin").unwrap(), b"stored data");
+
+    let mut out = vec!();
+    assert_eq!(arc.get_file_into("a/stored.bin", &mut out).unwrap(), 11);
+    assert_eq!(out, b"stored data");
+    assert!(arc.verify_file(hash40("a/compressed.bin")).unwrap());
+    assert!(arc.verify_file(hash40("a/stored.bin")).unwrap());
+}
+
+#[test]
+fn verify_file() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::zstd("a/valid.bin", b"valid"),
+        TestFile { stored: Some(zstd::block::compress(b"longer than stored", 3).unwrap()), ..TestFile::zstd("a/too_long.bin", b"short") },
+        TestFile { stored: Some(b"not zstd data".to_vec()), ..TestFile::zstd("a/garbage.bin", b"garbage") },
+    )).open();
+
+    assert!(arc.verify_file(hash40("a/valid.bin")).unwrap());
+    assert!(!arc.verify_file(hash40("a/too_long.bin")).unwrap());
+    assert!(matches!(arc.verify_file(hash40("a/garbage.bin")), Err(GetFileError::Decompress(_))));
+    assert!(matches!(arc.verify_file(hash40("a/missing.bin")), Err(GetFileError::FileNotFound)));
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn zstd_and_stored_files_mmap() {
+    let path = temp_dir("mmap").join("data.arc");
+    TestArchive::new(vec!(
+        TestFile::zstd("a/compressed.bin", &[2; 0x1000]),
+        TestFile::stored("a/stored.bin", b"stored data"),
+    )).write_to(&path);
+
+    let mut arc = unsafe { DataArc::new_mmap(File::open(&path).unwrap()).unwrap() };
+    assert_eq!(arc.get_file("a/compressed.bin").unwrap(), vec!(2; 0x1000));
+    assert_eq!(arc.get_file("a/stored.bin").unwrap(), b"stored data");
+}
+
+#[test]
+fn redirects() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::zstd("a/target.bin", b"target"),
+        TestFile { tree_flags: REDIRECT, suboffset_index: Some(0), ..TestFile::stored("a/redirect.bin", b"") },
+        TestFile { tree_flags: REDIRECT, suboffset_index: Some(1), ..TestFile::stored("a/redirect_to_redirect.bin", b"") },
+        TestFile { tree_flags: REDIRECT, ..TestFile::stored("a/cycle.bin", b"") },
+        TestFile { tree_flags: REDIRECT, suboffset_index: Some(5), ..TestFile::stored("a/out_of_range.bin", b"") },
+    )).open();
+
+    let target = arc.get_file("a/target.bin").unwrap();
+    assert_eq!(target, b"target");
+    assert_eq!(arc.get_file("a/redirect.bin").unwrap(), target);
+    assert_eq!(arc.get_file("a/redirect_to_redirect.bin").unwrap(), target);
+    assert_eq!(arc.file_metadata("a/redirect.bin").unwrap(), arc.file_metadata("a/target.bin").unwrap());
+    assert!(matches!(arc.get_file("a/cycle.bin"), Err(GetFileError::Corrupt(_))));
+    assert!(matches!(arc.get_file("a/out_of_range.bin"), Err(GetFileError::Corrupt(_))));
+    assert_eq!(arc.problem_files(), vec!(
+        (hash40("a/cycle.bin"), ProblemKind::Redirect),
+        (hash40("a/out_of_range.bin"), ProblemKind::Redirect),
+    ));
+}
+
+fn extract_all_archive() -> TestArchive {
+    TestArchive::new(vec!(
+        TestFile::zstd("prebuilt:/a/compressed.bin", b"compressed"),
+        TestFile::stored("a/stored.bin", b"stored"),
+        TestFile::stored("unlabeled.bin", b"unlabeled"),
+        TestFile { tree_flags: REDIRECT, suboffset_index: Some(1), ..TestFile::stored("a/redirect.bin", b"") },
+    ))
+}
+
+#[test]
+fn extract_all_loaded_labels() {
+    let out_dir = temp_dir("extract_all_loaded_labels");
+    let mut arc = extract_all_archive().open();
+    assert_eq!(arc.load_labels(&b"prebuilt:/a/compressed.bin\r\na/stored.bin\n\na/redirect.bin\n"[..]).unwrap(), 3);
+    assert_eq!(arc.labels().len(), 3);
+
+    let report = arc.extract_all(&out_dir, None, &ExtractOptions::default()).unwrap();
+    assert_eq!(report, ExtractReport { succeeded: 4, skipped: vec!(), failed: vec!(), shared: vec!(), sidecars: vec!() });
+    assert_eq!(fs::read(out_dir.join("prebuilt_/a/compressed.bin")).unwrap(), b"compressed");
+    assert_eq!(fs::read(out_dir.join("a/stored.bin")).unwrap(), b"stored");
+    assert_eq!(fs::read(out_dir.join(format!("0x{:x}.bin", hash40("unlabeled.bin")))).unwrap(), b"unlabeled");
+    // Redirects are extracted with their target's data
+    assert_eq!(fs::read(out_dir.join("a/redirect.bin")).unwrap(), b"stored");
+}
+
+#[test]
+fn extract_all_passed_labels_and_sanitizer() {
+    let out_dir = temp_dir("extract_all_passed_labels");
+    let mut arc = extract_all_archive().open();
+    arc.load_labels(&b"a/stored.bin\n"[..]).unwrap();
+
+    // The passed labels are used instead of the loaded ones
+    let mut labels = HashMap::new();
+    labels.insert(hash40("prebuilt:/a/compressed.bin"), String::from("prebuilt:/a/compressed.bin"));
+    let options = ExtractOptions { sanitizer: PathSanitizer::default().map(':', '-'), ..ExtractOptions::default() };
+    let report = arc.extract_all(&out_dir, Some(&labels), &options).unwrap();
+    assert_eq!(report.succeeded, 4);
+    assert_eq!(fs::read(out_dir.join("prebuilt-/a/compressed.bin")).unwrap(), b"compressed");
+    assert_eq!(fs::read(out_dir.join(format!("0x{:x}.bin", hash40("a/stored.bin")))).unwrap(), b"stored");
+}
+
+#[test]
+fn extract_all_unknown_dir() {
+    let out_dir = temp_dir("extract_all_unknown_dir");
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("a/labeled.numdlb", b"labeled"),
+        TestFile::stored("b/unlabeled.numdlb", b"extension from a labeled path"),
+        TestFile::stored("c/unlabeled.nutexb", b"extension from an extension label"),
+        TestFile::stored("d/unlabeled.xyz", b"unknown extension"),
+    )).open();
+    arc.load_labels(&b"a/labeled.numdlb\nnutexb\n"[..]).unwrap();
+
+    let options = ExtractOptions { unknown_dir: true, ..ExtractOptions::default() };
+    assert_eq!(arc.extract_all(&out_dir, None, &options).unwrap().succeeded, 4);
+    let unknown = |path: &str, ext: &str| out_dir.join("_unknown").join(format!("0x{:x}.{}", hash40(path), ext));
+    assert_eq!(fs::read(out_dir.join("a/labeled.numdlb")).unwrap(), b"labeled");
+    assert_eq!(fs::read(unknown("b/unlabeled.numdlb", "numdlb")).unwrap(), b"extension from a labeled path");
+    assert_eq!(fs::read(unknown("c/unlabeled.nutexb", "nutexb")).unwrap(), b"extension from an extension label");
+    assert_eq!(fs::read(unknown("d/unlabeled.xyz", "bin")).unwrap(), b"unknown extension");
+}
+
+#[test]
+fn extract_all_shared_files() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::zstd("a/first.bin", b"shared"),
+        TestFile { share: Some(0), ..TestFile::zstd("b/second.bin", b"shared") },
+        TestFile::zstd("c/other.bin", b"other"),
+    )).open();
+    arc.load_labels(&b"a/first.bin\nb/second.bin\nc/other.bin\n"[..]).unwrap();
+    let first = hash40("a/first.bin");
+    let second = hash40("b/second.bin");
+    let offset = arc.file_metadata_by_hash(first).unwrap().offset;
+    assert_eq!(arc.paths_at_offset(offset), vec!(first, second));
+
+    let out_dir = temp_dir("extract_all_shared_write");
+    let report = arc.extract_all(&out_dir, None, &ExtractOptions::default()).unwrap();
+    assert_eq!((report.succeeded, report.shared), (3, vec!()));
+    assert_eq!(fs::read(out_dir.join("b/second.bin")).unwrap(), b"shared");
+
+    let out_dir = temp_dir("extract_all_shared_skip");
+    let options = ExtractOptions { shared_files: SharedFiles::Skip, ..ExtractOptions::default() };
+    let report = arc.extract_all(&out_dir, None, &options).unwrap();
+    assert_eq!((report.succeeded, report.shared), (2, vec!(second)));
+    assert_eq!(fs::read(out_dir.join("a/first.bin")).unwrap(), b"shared");
+    assert!(!out_dir.join("b/second.bin").exists());
+
+    let out_dir = temp_dir("extract_all_shared_hard_link");
+    let options = ExtractOptions { shared_files: SharedFiles::HardLink, ..ExtractOptions::default() };
+    // Extracting twice replaces the existing links
+    for _ in 0..2 {
+        let report = arc.extract_all(&out_dir, None, &options).unwrap();
+        assert_eq!((report.succeeded, report.shared), (3, vec!(second)));
+    }
+    assert_eq!(fs::read(out_dir.join("b/second.bin")).unwrap(), b"shared");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let first = fs::metadata(out_dir.join("a/first.bin")).unwrap();
+        let second = fs::metadata(out_dir.join("b/second.bin")).unwrap();
+        assert_eq!((first.dev(), first.ino()), (second.dev(), second.ino()));
+        assert_eq!(first.nlink(), 2);
+    }
+}
+
+#[test]
+fn extract_all_best_effort() {
+    let corrupt_data = b"not a zstd frame".to_vec();
+    let mut arc = TestArchive::new(vec!(
+        TestFile::zstd("a/first.bin", b"first"),
+        TestFile { stored: Some(corrupt_data.clone()), ..TestFile::zstd("a/corrupt.bin", b"corrupt") },
+        TestFile::stored("a/last.bin", b"last"),
+    )).open();
+    arc.load_labels(&b"a/first.bin\na/corrupt.bin\na/last.bin\n"[..]).unwrap();
+    let corrupt = hash40("a/corrupt.bin");
+
+    let out_dir = temp_dir("extract_all_not_best_effort");
+    let report = arc.extract_all(&out_dir, None, &ExtractOptions::default()).unwrap();
+    assert_eq!((report.succeeded, &report.failed, &report.sidecars), (2, &vec!(corrupt), &vec!()));
+    assert!(!out_dir.join("a/corrupt.bin.zst").exists());
+
+    let out_dir = temp_dir("extract_all_best_effort");
+    let options = ExtractOptions { best_effort: true, ..ExtractOptions::default() };
+    let report = arc.extract_all(&out_dir, None, &options).unwrap();
+    assert_eq!((report.succeeded, &report.failed, &report.sidecars), (2, &vec!(corrupt), &vec!(corrupt)));
+    assert_eq!(fs::read(out_dir.join("a/first.bin")).unwrap(), b"first");
+    assert_eq!(fs::read(out_dir.join("a/last.bin")).unwrap(), b"last");
+    assert!(!out_dir.join("a/corrupt.bin").exists());
+    assert_eq!(fs::read(out_dir.join("a/corrupt.bin.zst")).unwrap(), corrupt_data);
+}
+
+#[test]
+fn extract_with_labels() {
+    let dir = temp_dir("extract");
+    let archive_path = dir.join("data.arc");
+    let labels_path = dir.join("Hashes.txt");
+    let out_dir = dir.join("out");
+    extract_all_archive().write_to(&archive_path);
+    fs::write(&labels_path, "prebuilt:/a/compressed.bin\na/stored.bin\n").unwrap();
+
+    let report = extract(&archive_path, &out_dir, Some(&labels_path)).unwrap();
+    assert_eq!((report.succeeded, report.skipped.len(), report.failed.len()), (4, 0, 0));
+    assert_eq!(fs::read(out_dir.join("prebuilt_/a/compressed.bin")).unwrap(), b"compressed");
+    assert_eq!(fs::read(out_dir.join("a/stored.bin")).unwrap(), b"stored");
+    assert_eq!(fs::read(out_dir.join(format!("0x{:x}.bin", hash40("unlabeled.bin")))).unwrap(), b"unlabeled");
+
+    let out_dir = dir.join("out_unlabeled");
+    assert_eq!(extract(&archive_path, &out_dir, None).unwrap().succeeded, 4);
+    assert!(out_dir.join(format!("0x{:x}.bin", hash40("a/stored.bin"))).exists());
+
+    assert!(matches!(extract(&archive_path, &out_dir, Some(&dir.join("missing.txt"))), Err(ExtractError::Labels(_))));
+    assert!(matches!(extract(&labels_path, &out_dir, None), Err(ExtractError::Parse(_))));
+}
+
+#[test]
+fn read_folder() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile::zstd("a/second.bin", b"second"),
+        TestFile { stored: Some(b"not zstd data".to_vec()), ..TestFile::zstd("a/corrupt.bin", b"corrupt") },
+        TestFile::stored("a/b/nested.bin", b"nested"),
+        TestFile::stored("c/other.bin", b"other"),
+    )).open();
+
+    let contents = arc.read_folder(hash40("a")).unwrap();
+    let expected: HashMap<u64, Vec<u8>> = vec!(
+        (hash40("a/first.bin"), b"first".to_vec()),
+        (hash40("a/second.bin"), b"second".to_vec()),
+    ).into_iter().collect();
+    assert_eq!(contents.files, expected);
+    assert_eq!(contents.failed, vec!(hash40("a/corrupt.bin")));
+
+    assert_eq!(arc.read_folder(hash40("missing")).unwrap(), FolderContents::default());
+}
+
+#[test]
+fn get_file_full() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::zstd("a/compressed.bin", &[3; 0x200]),
+        TestFile::stored("a/stored.bin", b"stored"),
+    )).open();
+
+    for path in &["a/compressed.bin", "a/stored.bin"] {
+        let hash = hash40(path);
+        let (data, metadata) = arc.get_file_full(hash).unwrap();
+        assert_eq!(data, arc.get_file(path).unwrap());
+        assert_eq!(metadata.decomp_size as usize, data.len());
+        assert_eq!(metadata, arc.file_metadata(path).unwrap());
+    }
+    assert!(matches!(arc.get_file_full(hash40("a/missing.bin")), Err(GetFileError::FileNotFound)));
+}
+
+#[test]
+fn split_archive() {
+    let compressed: Vec<u8> = (0..0x2000).map(|i| (i * 13 % 251) as u8).collect();
+    let archive = TestArchive::new(vec!(
+        TestFile::zstd("a/compressed.bin", &compressed),
+        TestFile::stored("a/stored.bin", b"stored data"),
+    ));
+    let data = archive.build();
+    let metadata = archive.open().file_metadata("a/compressed.bin").unwrap();
+
+    // Split in the middle of a/compressed.bin's data
+    let split = (metadata.offset + metadata.comp_size as u64 / 2) as usize;
+    let parts = vec!(Cursor::new(data[..split].to_vec()), Cursor::new(data[split..].to_vec()));
+    let mut arc = DataArc::new(SplitReader::new(parts).unwrap()).unwrap();
+    assert_eq!(arc.get_file("a/compressed.bin").unwrap(), compressed);
+    assert_eq!(arc.get_file("a/stored.bin").unwrap(), b"stored data");
+
+    let dir = temp_dir("split_archive");
+    let paths = vec!(dir.join("data.arc.part0"), dir.join("data.arc.part1"));
+    fs::write(&paths[0], &data[..split]).unwrap();
+    fs::write(&paths[1], &data[split..]).unwrap();
+    let mut arc = DataArc::new(SplitReader::open(&paths).unwrap()).unwrap();
+    assert_eq!(arc.get_file("a/compressed.bin").unwrap(), compressed);
+}
+
+#[test]
+fn get_file_cow() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::zstd("a/compressed.bin", &[4; 0x200]),
+        TestFile::stored("a/stored.bin", b"stored"),
+    )).open();
+
+    // Without a memory map there is nothing to borrow from
+    assert!(matches!(arc.get_file_cow(hash40("a/compressed.bin")).unwrap(), Cow::Owned(data) if data == [4; 0x200]));
+    assert!(matches!(arc.get_file_cow(hash40("a/stored.bin")).unwrap(), Cow::Owned(data) if data == b"stored"));
+    assert!(matches!(arc.get_file_cow(hash40("a/missing.bin")), Err(GetFileError::FileNotFound)));
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn get_file_cow_mmap() {
+    let path = temp_dir("get_file_cow_mmap").join("data.arc");
+    TestArchive::new(vec!(
+        TestFile::zstd("a/compressed.bin", &[4; 0x200]),
+        TestFile::stored("a/stored.bin", b"stored"),
+    )).write_to(&path);
+
+    let mut arc = unsafe { DataArc::new_mmap(File::open(&path).unwrap()).unwrap() };
+    assert!(matches!(arc.get_file_cow(hash40("a/compressed.bin")).unwrap(), Cow::Owned(data) if data == [4; 0x200]));
+    assert!(matches!(arc.get_file_cow(hash40("a/stored.bin")).unwrap(), Cow::Borrowed(b"stored")));
+    assert!(matches!(arc.get_file_cow(hash40("a/missing.bin")), Err(GetFileError::FileNotFound)));
+}
+
+#[test]
+fn get_file_schemes() {
+    let archive = TestArchive {
+        bulk_files: vec!(("stream:/sound/bgm/bgm_a.nus3audio".to_string(), b"bulk music".to_vec())),
+        ..TestArchive::new(vec!(
+            TestFile::zstd("prebuilt:/a/compressed.bin", &[5; 0x100]),
+            TestFile::stored("a/stored.bin", b"stored"),
+            TestFile::stored("stream:/a/in_file_section.bin", b"file section"),
+        ))
+    };
+    let mut arc = archive.open();
+
+    assert_eq!(arc.get_file("stream:/sound/bgm/bgm_a.nus3audio").unwrap(), b"bulk music");
+    assert_eq!(arc.get_file("stream:/a/in_file_section.bin").unwrap(), b"file section");
+    assert_eq!(arc.get_file("prebuilt:/a/compressed.bin").unwrap(), vec!(5; 0x100));
+    assert_eq!(arc.get_file("a/stored.bin").unwrap(), b"stored");
+    assert!(matches!(arc.get_file("stream:/sound/bgm/missing.nus3audio"), Err(GetFileError::FileNotFound)));
+    // Bulk files are only looked up by stream:/ paths
+    assert!(matches!(arc.get_file("prebuilt:/sound/bgm/bgm_a.nus3audio"), Err(GetFileError::FileNotFound)));
+
+    let mut out = vec!();
+    assert_eq!(arc.get_file_into("stream:/sound/bgm/bgm_a.nus3audio", &mut out).unwrap(), 10);
+    assert_eq!(out, b"bulk music");
+}
+
+#[test]
+fn build_tree() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("prebuilt:/a/b/one.bin", b"one"),
+        TestFile::zstd("prebuilt:/a/two.bin", b"two"),
+        TestFile::stored("c/three.bin", b"three"),
+        TestFile::stored("four.bin", b"four"),
+    )).open();
+    arc.load_labels(&b"prebuilt:/a/b/one.bin\nprebuilt:/a/two.bin\nc/three.bin\n"[..]).unwrap();
+
+    let mut file = |name: &str, path: &str| PathNode {
+        name: name.to_string(),
+        hash: hash40(path),
+        kind: PathNodeKind::File(Some(arc.file_metadata(path).unwrap())),
+    };
+    let one = file("one.bin", "prebuilt:/a/b/one.bin");
+    let two = file("two.bin", "prebuilt:/a/two.bin");
+    let three = file("three.bin", "c/three.bin");
+    let four = PathNode { name: format!("0x{:x}", hash40("four.bin")), ..file("", "four.bin") };
+    let folder = |name: &str, path: &str, children: Vec<PathNode>| PathNode {
+        name: name.to_string(),
+        hash: hash40(path),
+        kind: PathNodeKind::Folder(children),
+    };
+    let expected = folder("", "", vec!(
+        four,
+        folder("c", "c", vec!(three)),
+        folder("prebuilt:", "prebuilt:", vec!(
+            folder("a", "prebuilt:/a", vec!(
+                folder("b", "prebuilt:/a/b", vec!(one)),
+                two,
+            )),
+        )),
+    ));
+    assert_eq!(arc.build_tree(None), expected);
+}
+
+#[test]
+fn entries_with_unknown_ext() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("a/labeled.numdlb", b"labeled"),
+        TestFile::stored("b/unlabeled.numdlb", b"extension from a labeled path"),
+        TestFile::stored("c/unlabeled.nutexb", b"extension from an extension label"),
+        TestFile::stored("d/unlabeled.xyz", b"unknown extension"),
+        TestFile { ext_hash: Some(0), ..TestFile::stored("e/zero.numdlb", b"zero extension hash") },
+    )).open();
+    assert_eq!(arc.entries_with_unknown_ext().len(), 5);
+
+    arc.load_labels(&b"a/labeled.numdlb\nnutexb\n"[..]).unwrap();
+    assert_eq!(arc.entries_with_unknown_ext(), vec!(hash40("d/unlabeled.xyz"), hash40("e/zero.numdlb")));
+}
+
+#[test]
+fn section_padding() {
+    let files = vec!(TestFile::stored("a/first.bin", b"first"), TestFile::stored("a/second.bin", b"second"));
+    let corrupt_message = |padding: Vec<u8>| {
+        let archive = TestArchive { padding: vec!(("file_lookup_buckets", padding)), ..TestArchive::new(files.clone()) };
+        match DataArc::new(Cursor::new(archive.build())) {
+            Err(ParseError::Corrupt(message)) => message,
+            result => panic!("{:?}", result.err()),
+        }
+    };
+
+    // Zero padding reads as a bucket table header with no buckets
+    let message = corrupt_message(vec!(0; 8));
+    assert!(message.contains("file_lookup_buckets") && message.contains("padding"), "{}", message);
+
+    // Other padding reads as a bucket table running past the end of the node section
+    let message = corrupt_message(vec!(0xff; 8));
+    assert!(message.contains("The file_lookup_buckets section ends") && message.contains("padding"), "{}", message);
+}
+
+#[test]
+fn node_section_offset_past_eof() {
+    let data = TestArchive::new(vec!(TestFile::stored("a.bin", b"a"))).build();
+    for offset in &[data.len() as u64, data.len() as u64 + 0x100, u64::MAX] {
+        let mut data = data.clone();
+        data[0x20..0x28].copy_from_slice(&offset.to_le_bytes());
+        match DataArc::new(Cursor::new(data)) {
+            Err(ParseError::Corrupt(message)) => assert!(message.contains("node section offset"), "{}", message),
+            result => panic!("0x{:x}: {:?}", offset, result.err()),
+        }
+    }
+}
+
+#[test]
+fn truncated_headers() {
+    let data = TestArchive::new(vec!(TestFile::stored("a.bin", b"a"))).build();
+    let node_section_offset = u64::from_le_bytes(data[0x20..0x28].try_into().unwrap()) as usize;
+
+    // Inside the node section
+    match DataArc::new(Cursor::new(data[..node_section_offset + 0x20].to_vec())) {
+        Err(ParseError::Corrupt(message)) => assert!(message.contains(&format!("0x{:x}", node_section_offset)), "{}", message),
+        result => panic!("{:?}", result.err()),
+    }
+
+    // Inside the ArcHeader, which starts after the 8 byte magic
+    match DataArc::new(Cursor::new(data[..0x10].to_vec())) {
+        Err(ParseError::Io(err)) => assert!(err.to_string().contains("0x8"), "{}", err),
+        result => panic!("{:?}", result.err()),
+    }
+}
+
+#[test]
+fn truncated_node_buffer() {
+    let archive = TestArchive {
+        bulk_files: vec!(("stream:/a/bulk.bin".to_string(), b"bulk".to_vec())),
+        ..TestArchive::new(vec!(
+            TestFile::zstd("a/compressed.bin", b"compressed"),
+            TestFile::stored("a/stored.bin", b"stored"),
+        ))
+    };
+    let len = archive.open().buffer.len();
+    let files: &[(&str, &[u8])] = &[("a/compressed.bin", b"compressed"), ("a/stored.bin", b"stored"), ("stream:/a/bulk.bin", b"bulk")];
+
+    for truncated_len in 0..len {
+        let mut arc = archive.open();
+        arc.buffer.truncate(truncated_len);
+
+        // Every read site must fail cleanly or still return the right data, never panic
+        for (path, data) in files {
+            match arc.get_file(path) {
+                Ok(file) => assert_eq!(&file, data),
+                Err(err) => assert!(matches!(err, GetFileError::Corrupt(_)), "{}: {:?}", truncated_len, err),
+            }
+            let hash = hash40(path);
+            let _ = arc.file_metadata_by_hash(hash);
+            let _ = arc.get_file_range(hash, 0..1);
+            let _ = arc.verify_file(hash);
+            let _ = arc.required_big_files(&[hash]);
+            let _ = arc.tree_entry(hash);
+            let _ = arc.find_by_file_hash(hash);
+        }
+        // The last table is cut short by any truncation
+        assert!(arc.sections().is_err());
+        arc.trace_all(&mut io::sink()).unwrap();
+        let _ = arc.sanity_probe();
+        let _ = arc.problem_files();
+        let _ = arc.entries_by_bucket();
+        let _ = arc.flag_value_histogram();
+        let _ = arc.packing_efficiency();
+        let _ = arc.max_bucket_len();
+        let _ = arc.check_alignment(4);
+        let _ = arc.paths_at_offset(0);
+        let _ = arc.entries_with_unknown_ext();
+        let _ = arc.file_lookup_entries().count();
+        let _ = arc.content_dedup();
+        let _ = arc.build_tree(None);
+        let _ = arc.read_folder(hash40("a"));
+    }
+}
+
+#[test]
+fn overflowing_counts() {
+    let mut data = TestArchive::new(vec!(TestFile::stored("a.bin", b"a"))).build();
+    // file_count1, sub_files1_count, file_count2 and sub_files2_count
+    for offset in &[0x8, 0x10, 0x20, 0x24] {
+        data = patch_node(data, *offset, u32::MAX);
+    }
+    assert!(matches!(DataArc::new(Cursor::new(data)), Err(ParseError::Corrupt(_))));
+}
+
+/// The archive's data with the u32 at `offset` from the start of the node section replaced with `value`
+fn patch_node(mut data: Vec<u8>, offset: usize, value: u32) -> Vec<u8> {
+    let node_section_offset = u64::from_le_bytes(data[0x20..0x28].try_into().unwrap()) as usize;
+    data[node_section_offset + offset..node_section_offset + offset + 4].copy_from_slice(&value.to_le_bytes());
+    data
+}
+
+#[test]
+fn compressed_node_decomp_size_is_bounded() {
+    let archive = TestArchive { compress_node: true, ..TestArchive::new(vec!(TestFile::stored("a.bin", b"a"))) };
+    // Without the bound this would allocate 4 GiB before attempting to decompress
+    let data = patch_node(archive.build(), 4, u32::MAX);
+    assert!(matches!(DataArc::new(Cursor::new(data)), Err(ParseError::Corrupt(_))));
+}
+
+#[test]
+fn ambiguous_node_section() {
+    // An uncompressed node section whose NodeHeader also reads as a consistent CompressedNodeHeader:
+    // `file_size`, `folder_count`, `file_count1` and `tree_count` are `data_start`, `decomp_size`, `comp_size` and `zstd_comp_size`
+    let files = (0..10).map(|i| TestFile::stored(&format!("{}.bin", i), &[0; 0x10])).collect();
+    let mut data = TestArchive::new(files).build();
+    for (offset, value) in &[(0x0, 0x80), (0x4, 0x100), (0x8, 0x40), (0xc, 0x20)] {
+        data = patch_node(data, *offset, *value);
+    }
+
+    // Decompressing fails so it is read as uncompressed, which the counts are inconsistent with
+    match DataArc::new(Cursor::new(data)) {
+        Err(ParseError::Corrupt(message)) => assert!(message.contains("big_hashes"), "{}", message),
+        result => panic!("{:?}", result.err()),
+    }
+}
+
+#[test]
+fn open_progress() {
+    let data = TestArchive::new(vec!(TestFile::stored("a.bin", b"a"))).build();
+    let mut stages = vec!();
+    DataArc::new_with_progress(Cursor::new(data), |stage| stages.push(stage)).unwrap();
+    assert_eq!(stages, vec!(OpenStage::HeaderRead, OpenStage::NodeRead, OpenStage::NodeParsed));
+}
+
+#[test]
+fn read_at() {
+    let mut arc = TestArchive::new(vec!(TestFile::stored("a.bin", b"stored data"))).open();
+    assert_eq!(arc.read_at(0, 8).unwrap(), 0xabcdef9876543210u64.to_le_bytes());
+    // The only file is at the start of the file section, which directly follows the ArcHeader
+    assert_eq!(arc.read_at(0x30, 11).unwrap(), b"stored data");
+
+    let len = arc.file_len;
+    assert_eq!(arc.read_at(len, 0).unwrap(), b"");
+    assert_eq!(arc.read_at(len - 1, 2).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(arc.read_at(u64::MAX, 1).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn sections() {
+    let arc = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile::zstd("a/second.bin", b"second"),
+    )).open();
+    let sections = arc.sections().unwrap();
+
+    assert_eq!(sections.big_hashes.path.hash, hash40(""));
+    assert_eq!((sections.big_files.offset, sections.big_files.files), (0, 2));
+    assert_eq!(sections.trees.path.hash, hash40("a/first.bin"));
+    assert_eq!(sections.trees.folder.hash, hash40("a"));
+    assert_eq!(sections.trees.file.hash, hash40("first.bin"));
+    assert_eq!(sections.trees.ext.hash, hash40("bin"));
+    assert_eq!((sections.sub_files1.offset, sections.sub_files1.comp_size, sections.sub_files1.decomp_size, sections.sub_files1.flags), (0, 5, 5, 0));
+    // The header bucket of a single bucket table
+    assert_eq!((sections.file_lookup_buckets.index, sections.file_lookup_buckets.num_entries), (0, 1));
+    // Entries within a bucket are sorted by hash
+    assert_eq!(sections.file_lookup.hash, hash40("a/first.bin").min(hash40("a/second.bin")));
+}
+
+#[test]
+fn trace_all() {
+    let mut redirect = TestFile::stored("a/redirect.bin", b"");
+    redirect.tree_flags = REDIRECT;
+    redirect.suboffset_index = Some(100);
+    let arc = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile::zstd("a/second.bin", b"second"),
+        redirect,
+    )).open();
+
+    let mut out = vec!();
+    arc.trace_all(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), arc.node_header.tree_count as usize);
+
+    assert!(lines[0].starts_with(&format!("tree=0 hash=0x{:x} ", hash40("a/first.bin"))), "{}", lines[0]);
+    assert!(lines[0].contains(" sub_file.comp_size=0x5 ") && lines[0].ends_with(" offset=0x30"), "{}", lines[0]);
+    assert!(lines[1].starts_with("tree=1 ") && !lines[1].contains("error="), "{}", lines[1]);
+    // The redirect's target is out of range
+    assert!(lines[2].starts_with("tree=2 ") && lines[2].contains(" error="), "{}", lines[2]);
+}
+
+#[test]
+fn find_by_file_hash() {
+    let arc = TestArchive::new(vec!(
+        TestFile::stored("a/same.bin", b"a"),
+        TestFile::stored("b/same.bin", b"b"),
+        TestFile::stored("a/other.bin", b"other"),
+    )).open();
+
+    let mut found = arc.find_by_file_hash(hash40("same.bin"));
+    found.sort_unstable();
+    let mut expected = vec!(hash40("a/same.bin"), hash40("b/same.bin"));
+    expected.sort_unstable();
+    assert_eq!(found, expected);
+    assert_eq!(arc.find_by_file_hash(hash40("other.bin")), vec!(hash40("a/other.bin")));
+    assert!(arc.find_by_file_hash(hash40("missing.bin")).is_empty());
+}
+
+#[test]
+fn entries_by_bucket() {
+    let files = (0..40).map(|i| TestFile::stored(&format!("folder/file_{}.bin", i), b"")).collect();
+    let arc = TestArchive { num_buckets: Some(7), ..TestArchive::new(files) }.open();
+
+    let buckets = arc.entries_by_bucket();
+    assert_eq!(buckets.len(), 7);
+    for (i, bucket) in buckets.iter().enumerate() {
+        for hash in bucket {
+            assert_eq!(hash % 7, i as u64, "0x{:x}", hash);
+        }
+    }
+
+    let mut all: Vec<u64> = buckets.into_iter().flatten().collect();
+    all.sort_unstable();
+    let mut expected: Vec<u64> = (0..40).map(|i| hash40(&format!("folder/file_{}.bin", i))).collect();
+    expected.sort_unstable();
+    assert_eq!(all, expected);
+}
+
+#[test]
+fn bucket_table_layout() {
+    let paths: Vec<String> = (0..20).map(|i| format!("folder/file_{}.bin", i)).collect();
+    let files: Vec<TestFile> = paths.iter().map(|path| TestFile::stored(path, b"")).collect();
+    let file_lookup: Vec<EntryPair> = paths.iter().enumerate()
+        .map(|(i, path)| EntryPair { hash: hash40(path), meta: i as u32 })
+        .collect();
+
+    for num_buckets in &[1, 7] {
+        let arc = TestArchive { num_buckets: Some(*num_buckets), ..TestArchive::new(files.clone()) }.open();
+        assert_eq!(arc.bucket_count(), *num_buckets);
+        // The header bucket followed by the buckets
+        assert_eq!(arc.file_lookup, arc.file_lookup_buckets + 8 * (*num_buckets as usize + 1));
+
+        let expected = build_hash_buckets(&file_lookup, *num_buckets);
+        assert_eq!(&arc.buffer[arc.file_lookup_buckets..arc.file_lookup], &expected.file_lookup_buckets[..]);
+        assert_eq!(&arc.buffer[arc.file_lookup..arc.file_lookup + 8 * paths.len()], &expected.file_lookup[..]);
+    }
+}
+
+#[test]
+fn bucket_statistics() {
+    let hashes: Vec<u64> = (0..40).map(|i| hash40(&format!("folder/file_{}.bin", i))).collect();
+    let files = (0..40).map(|i| TestFile::stored(&format!("folder/file_{}.bin", i), b"")).collect();
+    let arc = TestArchive { num_buckets: Some(7), ..TestArchive::new(files) }.open();
+
+    let mut counts = [0; 7];
+    for hash in &hashes {
+        counts[(hash % 7) as usize] += 1;
+    }
+    assert_eq!(arc.max_bucket_len(), *counts.iter().max().unwrap());
+    assert!((arc.bucket_load_factor() - 40.0 / 7.0).abs() < 1e-9, "{}", arc.bucket_load_factor());
+
+    let arc = TestArchive::new(vec!()).open();
+    assert_eq!(arc.max_bucket_len(), 0);
+    assert_eq!(arc.bucket_load_factor(), 0.0);
+}
+
+#[test]
+fn bucket_index() {
+    let mut arc = TestArchive { num_buckets: Some(7), ..TestArchive::new(vec!(TestFile::stored("a.bin", b"a"))) }.open();
+    assert_eq!(arc.bucket_index(0).unwrap(), 0);
+    assert_eq!(arc.bucket_index(u64::MAX).unwrap(), (u64::MAX % 7) as u32);
+    let multiple = 7 << 40;
+    assert_eq!(arc.bucket_index(multiple - 1).unwrap(), 6);
+    assert_eq!(arc.bucket_index(multiple).unwrap(), 0);
+    assert_eq!(arc.bucket_index(multiple + 1).unwrap(), 1);
+
+    arc.first_hash_bucket.num_entries = 0;
+    assert!(matches!(arc.bucket_index(0), Err(GetFileError::Corrupt(_))));
+}
+
+#[test]
+fn required_big_files() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile::zstd("a/second.bin", &[2; 0x100]),
+        // Uncompressed data is read using its decomp_size, even if its comp_size is larger
+        TestFile { stored: Some(b"last".iter().cycle().take(0x40).cloned().collect()), ..TestFile::stored("a/last.bin", b"last") },
+    )).open();
+    let first = arc.file_metadata("a/first.bin").unwrap();
+    let second = arc.file_metadata("a/second.bin").unwrap();
+    let last = arc.file_metadata("a/last.bin").unwrap();
+    assert_eq!((last.comp_size, last.decomp_size), (0x40, 4));
+
+    // Files in the same big file are merged into a single range
+    let hashes = [hash40("a/first.bin"), hash40("a/second.bin")];
+    assert_eq!(arc.required_big_files(&hashes).unwrap(), vec!((BigFileIndex(0), first.offset..second.offset + second.comp_size as u64)));
+    assert_eq!(arc.required_big_files(&[hash40("a/last.bin")]).unwrap(), vec!((BigFileIndex(0), last.offset..last.offset + 4)));
+    assert!(arc.required_big_files(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn tree_entry() {
+    let arc = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile::stored("b/second.nus3audio", b"second"),
+    )).open();
+
+    let tree = arc.tree_entry(hash40("b/second.nus3audio")).unwrap();
+    assert_eq!(tree.path.hash, hash40("b/second.nus3audio"));
+    assert_eq!(tree.folder.hash, hash40("b"));
+    assert_eq!(tree.file.hash, hash40("second.nus3audio"));
+    assert_eq!(tree.ext.hash, hash40("nus3audio"));
+    assert_eq!(tree.suboffset_index, 1);
+    assert!(arc.tree_entry(hash40("a/missing.bin")).is_none());
+}
+
+#[test]
+fn big_file_index() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile::zstd("b/second.bin", b"second"),
+    )).open();
+    // Every file of a TestArchive is in the only big file
+    assert_eq!(arc.big_file_index(hash40("a/first.bin")).unwrap(), BigFileIndex(0));
+    assert_eq!(arc.big_file_index(hash40("b/second.bin")).unwrap(), BigFileIndex(0));
+    assert!(matches!(arc.big_file_index(hash40("a/missing.bin")), Err(GetFileError::FileNotFound)));
+}
+
+#[test]
+fn packing_efficiency() {
+    let compressed: Vec<u8> = b"compressed ".iter().cycle().take(0x1000).cloned().collect();
+    let archive = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile::zstd("a/compressed.bin", &compressed),
+        TestFile::stored("a/aligned.bin", b"four"),
+    ));
+    let data = archive.build();
+    let mut arc = archive.open();
+
+    let report = arc.packing_efficiency();
+    let stored_sizes: Vec<u64> = archive.files.iter()
+        .map(|file| arc.file_metadata(&file.path).unwrap().comp_size as u64)
+        .collect();
+    assert_eq!(report.file_entry_count, 3);
+    assert_eq!(report.total_comp_size, stored_sizes.iter().sum::<u64>());
+    // The file section ends where the music file section starts
+    let music_file_section_offset = u64::from_le_bytes(data[0x8..0x10].try_into().unwrap());
+    assert_eq!(report.file_section_size, music_file_section_offset - 0x30);
+    // Each file is padded to 4 bytes
+    let padding: u64 = stored_sizes.iter().map(|size| (4 - size % 4) % 4).sum();
+    assert_eq!(report.unaccounted_bytes(), padding as i64);
+}
+
+#[test]
+fn music_section_sizes() {
+    let archive = TestArchive {
+        bulk_files: vec!((String::from("stream:/sound/bgm/a.nus3audio"), vec!(1; 0x20)), (String::from("stream:/sound/bgm/b.nus3audio"), vec!(2; 0x13))),
+        ..TestArchive::new(vec!(TestFile::stored("a.bin", b"a")))
+    };
+    let mut data = archive.build();
+    let node_section_offset = u64::from_le_bytes(data[0x20..0x28].try_into().unwrap());
+    let end = data.len() as u64;
+    // Trailing data after the last section, so the last section ending at the end of the data.arc is distinguishable
+    data.extend_from_slice(&[0; 0x10]);
+
+    let arc = DataArc::new(Cursor::new(data.clone())).unwrap();
+    // The music file section is followed by the music section, which shares its offset with the node section
+    assert_eq!(arc.music_file_section_size(), 0x20 + 0x14);
+    // The music section is followed by the unk section
+    assert_eq!(arc.music_section_size(), end - node_section_offset);
+
+    // With no section after it, the music section ends at the end of the data.arc
+    data[0x28..0x30].copy_from_slice(&0u64.to_le_bytes());
+    let arc = DataArc::new(Cursor::new(data)).unwrap();
+    assert_eq!(arc.music_section_size(), end + 0x10 - node_section_offset);
+}
+
+#[test]
+fn flag_value_histogram() {
+    let arc = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile::zstd("a/second.bin", b"second"),
+        TestFile::stored("a/third.bin", b"third"),
+        TestFile { file_flags: 0x10, ..TestFile::stored("a/fourth.bin", b"fourth") },
+    )).open();
+
+    let histogram = arc.flag_value_histogram();
+    let expected: BTreeMap<u32, usize> = vec!((0, 2), (0x10, 1), (ZSTD, 1)).into_iter().collect();
+    assert_eq!(histogram, expected);
+    assert_eq!(histogram.values().sum::<usize>() as u64, arc.node_header.sub_file_count());
+}
+
+#[test]
+fn get_file_by_hash_bytes() {
+    let mut arc = TestArchive::new(vec!(
+        TestFile::stored("a/first.bin", b"first"),
+        TestFile::zstd("a/second.bin", b"second"),
+    )).open();
+
+    for path in &["a/first.bin", "a/second.bin", "a/missing.bin"] {
+        let hash = hash40(path);
+        let bytes: [u8; 5] = hash.to_le_bytes()[..5].try_into().unwrap();
+        let by_bytes = arc.get_file_by_hash_bytes(bytes).map_err(|err| err.to_string());
+        let by_hash = arc.get_file_by_hash(hash).map_err(|err| err.to_string());
+        assert_eq!(by_bytes, by_hash, "{}", path);
+    }
+}
+
+#[test]
+fn file_lookup_entries() {
+    let files = (0..40).map(|i| TestFile::stored(&format!("folder/file_{}.bin", i), b"")).collect();
+    let arc = TestArchive { num_buckets: Some(7), ..TestArchive::new(files) }.open();
+
+    let entries: Vec<EntryPair> = arc.file_lookup_entries().collect();
+    assert_eq!(entries.len(), 40);
+    assert_eq!(entries.len(), arc.node_header.file_lookup_count as usize);
+    for i in 0..arc.bucket_count() {
+        let bucket = arc.bucket(i).unwrap();
+        let hashes: Vec<u64> = entries[bucket.index as usize..(bucket.index + bucket.num_entries) as usize].iter()
+            .map(|entry| entry.hash)
+            .collect();
+        assert!(hashes.windows(2).all(|pair| pair[0] < pair[1]), "bucket {}: {:x?}", i, hashes);
+    }
+    // Each entry points back at the tree entry with its hash
+    for entry in &entries {
+        assert_eq!(arc.tree(TreeIndex(entry.meta)).unwrap().path.hash, entry.hash);
+    }
+}
+
+#[test]
+fn magic() {
+    let arc = TestArchive::new(vec!(TestFile::stored("a.bin", b"a"))).open();
+    assert_eq!(arc.magic(), 0xabcdef9876543210);
+
+    let mut data = TestArchive::new(vec!(TestFile::stored("a.bin", b"a"))).build();
+    data[0] ^= 1;
+    assert!(matches!(DataArc::new(Cursor::new(data)), Err(ParseError::NotDataArc)));
+}
+
+/// Counts the reads made from the wrapped reader
+struct CountingReader<R> {
+    inner: R,
+    reads: Arc<AtomicUsize>,
+}
+
+impl<R: io::Read> io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.inner.read(buf)
+    }
+}
+
+impl<R: io::Seek> io::Seek for CountingReader<R> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn out_of_order_reads() {
+    let paths: Vec<String> = (0..50).map(|i| format!("a/{}.bin", i)).collect();
+    let files = paths.iter().enumerate()
+        .map(|(i, path)| if i % 2 == 0 { TestFile::stored(path, path.as_bytes()) } else { TestFile::zstd(path, path.as_bytes()) })
+        .collect();
+    let reads = Arc::new(AtomicUsize::new(0));
+    let reader = CountingReader { inner: Cursor::new(TestArchive::new(files).build()), reads: reads.clone() };
+    let mut arc = DataArc::new(reader).unwrap();
+
+    // Jump back and forth through the file section
+    let order: Vec<usize> = (0..25).flat_map(|i| vec!(i, 49 - i)).collect();
+    let reads_before = reads.load(Ordering::SeqCst);
+    for i in &order {
+        assert_eq!(arc.get_file(&paths[*i]).unwrap(), paths[*i].as_bytes());
+    }
+    for i in order.iter().rev() {
+        assert_eq!(arc.get_file(&paths[*i]).unwrap(), paths[*i].as_bytes());
+    }
+
+    // The whole file section fits in the read buffer, so nearly every file is read from the buffer
+    let file_reads = reads.load(Ordering::SeqCst) - reads_before;
+    assert!(file_reads < order.len() / 10, "{} reads for {} files", file_reads, order.len() * 2);
+}
+
+#[test]
+fn get_file_into() {
+    // Larger than the chunks data is copied in
+    let compressed: Vec<u8> = (0..0x30000u32).map(|i| (i % 251) as u8).collect();
+    let mut arc = TestArchive::new(vec!(
+        TestFile::zstd("a/compressed.bin", &compressed),
+        TestFile::stored("a/stored.bin", b"stored data"),
+    )).open();
+
+    let mut out = vec!();
+    assert_eq!(arc.get_file_into("a/compressed.bin", &mut out).unwrap(), compressed.len() as u64);
+    assert_eq!(out, compressed);
+
+    // Appends to what is already written
+    assert_eq!(arc.get_file_into("a/stored.bin", &mut out).unwrap(), 11);
+    assert_eq!(&out[compressed.len()..], b"stored data");
+    assert!(matches!(arc.get_file_into("a/missing.bin", &mut out), Err(GetFileError::FileNotFound)));
+    assert_eq!(out.len(), compressed.len() + 11);
+}